@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN email TEXT UNIQUE
\ No newline at end of file
@@ -0,0 +1,4 @@
+ALTER TABLE users
+    DROP COLUMN display_name,
+    DROP COLUMN locale,
+    DROP COLUMN time_zone
\ No newline at end of file
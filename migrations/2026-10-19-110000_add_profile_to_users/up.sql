@@ -0,0 +1,4 @@
+ALTER TABLE users
+    ADD COLUMN display_name TEXT,
+    ADD COLUMN locale TEXT,
+    ADD COLUMN time_zone TEXT
\ No newline at end of file
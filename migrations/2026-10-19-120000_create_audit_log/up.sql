@@ -0,0 +1,8 @@
+CREATE TABLE audit_log(
+    audit_id SERIAL PRIMARY KEY,
+    actor_id INTEGER REFERENCES users(user_id) ON DELETE SET NULL,
+    subject_id INTEGER REFERENCES users(user_id) ON DELETE SET NULL,
+    action TEXT NOT NULL,
+    details TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+)
\ No newline at end of file
@@ -0,0 +1,2 @@
+DROP TABLE oidc_identities;
+ALTER TABLE users DROP COLUMN guest
\ No newline at end of file
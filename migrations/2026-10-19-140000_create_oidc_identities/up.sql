@@ -0,0 +1,10 @@
+ALTER TABLE users ADD COLUMN guest BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE users SET guest = (login IS NULL OR password IS NULL);
+
+CREATE TABLE oidc_identities(
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (issuer, subject)
+)
\ No newline at end of file
@@ -0,0 +1,4 @@
+ALTER TABLE sessions
+    DROP COLUMN device_name,
+    DROP COLUMN ip_address,
+    DROP COLUMN last_seen_at
\ No newline at end of file
@@ -0,0 +1,4 @@
+ALTER TABLE sessions
+    ADD COLUMN device_name TEXT,
+    ADD COLUMN ip_address TEXT,
+    ADD COLUMN last_seen_at TIMESTAMP NOT NULL DEFAULT NOW()
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE parkings DROP COLUMN require_verified_email
\ No newline at end of file
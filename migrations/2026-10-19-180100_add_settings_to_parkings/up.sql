@@ -0,0 +1 @@
+ALTER TABLE parkings ADD COLUMN require_verified_email BOOLEAN NOT NULL DEFAULT FALSE
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE guest_recovery_codes
\ No newline at end of file
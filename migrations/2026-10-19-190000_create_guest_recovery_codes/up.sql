@@ -0,0 +1,5 @@
+CREATE TABLE guest_recovery_codes(
+    user_id INTEGER PRIMARY KEY REFERENCES users(user_id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+)
\ No newline at end of file
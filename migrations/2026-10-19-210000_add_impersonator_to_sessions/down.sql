@@ -0,0 +1 @@
+ALTER TABLE sessions DROP COLUMN impersonator_id
\ No newline at end of file
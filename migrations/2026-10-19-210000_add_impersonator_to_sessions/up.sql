@@ -0,0 +1 @@
+ALTER TABLE sessions ADD COLUMN impersonator_id INTEGER REFERENCES users(user_id) ON DELETE CASCADE
\ No newline at end of file
@@ -0,0 +1,2 @@
+DROP TABLE join_requests;
+ALTER TABLE parkings DROP COLUMN require_approval
\ No newline at end of file
@@ -0,0 +1,13 @@
+ALTER TABLE parkings ADD COLUMN require_approval BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE join_requests(
+    join_request_id SERIAL PRIMARY KEY,
+    parking_id INTEGER NOT NULL REFERENCES parkings(parking_id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    status TEXT NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    decided_at TIMESTAMP,
+    decided_by INTEGER REFERENCES users(user_id) ON DELETE SET NULL
+);
+
+CREATE UNIQUE INDEX join_requests_pending_idx ON join_requests(parking_id, user_id) WHERE status = 'pending'
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE parkings_consumers DROP COLUMN expires_at;
+ALTER TABLE parkings DROP COLUMN max_members
\ No newline at end of file
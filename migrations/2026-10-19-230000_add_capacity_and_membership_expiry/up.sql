@@ -0,0 +1,2 @@
+ALTER TABLE parkings ADD COLUMN max_members INTEGER;
+ALTER TABLE parkings_consumers ADD COLUMN expires_at TIMESTAMP
\ No newline at end of file
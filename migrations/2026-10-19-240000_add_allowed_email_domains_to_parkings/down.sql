@@ -0,0 +1 @@
+ALTER TABLE parkings DROP COLUMN allowed_email_domains
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE parkings ADD COLUMN allowed_email_domains TEXT[] NOT NULL DEFAULT '{}'
\ No newline at end of file
@@ -0,0 +1,2 @@
+DROP INDEX parkings_name_trgm_idx;
+ALTER TABLE parkings DROP COLUMN public
\ No newline at end of file
@@ -0,0 +1,4 @@
+ALTER TABLE parkings ADD COLUMN public BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX parkings_name_trgm_idx ON parkings USING GIN (name gin_trgm_ops)
\ No newline at end of file
@@ -0,0 +1,16 @@
+-- addresses that only differ in case or surrounding spaces would collide once normalized;
+-- the verified or oldest account keeps the address, the others lose it and have to add it again
+UPDATE users SET email = NULL, email_verified = false
+WHERE user_id IN (
+    SELECT user_id FROM (
+        SELECT user_id, row_number() OVER (
+            PARTITION BY lower(trim(email)) ORDER BY email_verified DESC, user_id
+        ) AS position
+        FROM users
+        WHERE email IS NOT NULL
+    ) ranked
+    WHERE position > 1
+);
+UPDATE users SET email = lower(trim(email)) WHERE email IS NOT NULL;
+ALTER TABLE users DROP CONSTRAINT users_email_key;
+CREATE UNIQUE INDEX users_email_lower_idx ON users (lower(email))
\ No newline at end of file
@@ -0,0 +1,183 @@
+use std::env;
+use std::fs::File;
+
+use crate::db::connection::establish_connection;
+use crate::handlers::api_key_handler::{create_api_key, list_api_keys, revoke_api_key, SCOPES};
+use crate::handlers::system_admin_handler::set_system_admin;
+use crate::handlers::user_import_handler::{import_users, ImportedUser};
+use crate::security::key_store;
+use crate::security::keys::{parse_algorithm, KeyError};
+
+const USAGE: &str = "usage:
+  user-service                                   start the server
+  user-service keys list                         show signing keys in JWT_KEYS_DIR
+  user-service keys generate [ALGORITHM]         stage a new ES256 (default), EdDSA or HS512 key
+  user-service keys import ALGORITHM PRIVATE [PUBLIC]
+                                                 stage a key from PEM files (e.g. RS256)
+  user-service keys activate KID                 sign new tokens with KID
+  user-service keys retire KID                   stop accepting tokens signed with KID
+  user-service api-keys list                     show service API keys
+  user-service api-keys create NAME SCOPE[,SCOPE]
+                                                 issue a key (scopes: introspect, memberships:read)
+  user-service api-keys revoke ID                disable a key
+  user-service users import FILE                 load users from a .csv or .json file with columns
+                                                 login, email, password_hash, display_name;
+                                                 argon2, bcrypt and PBKDF2 hashes are accepted
+  user-service users grant-admin LOGIN           give a user access to the /admin routes
+  user-service users revoke-admin LOGIN          take that access away again";
+
+/// Runs an administrative command and returns the process exit code.
+pub fn run(args: &[String]) -> i32 {
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    let result = match args.as_slice() {
+        ["keys", rest @ ..] => keys(rest).map_err(|e| e.to_string()),
+        ["api-keys", rest @ ..] => api_keys(rest),
+        ["users", rest @ ..] => users(rest),
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+/// Exits with the same code as an unknown command, so scripts notice a mistyped subcommand.
+fn usage_error() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2)
+}
+
+fn keys_dir() -> Result<String, KeyError> {
+    env::var("JWT_KEYS_DIR").map_err(|_| KeyError::MissingSetting("JWT_KEYS_DIR"))
+}
+
+fn keys(args: &[&str]) -> Result<(), KeyError> {
+    let dir = keys_dir()?;
+    match args {
+        ["list"] => {
+            let manifest = key_store::read_manifest(&dir)?;
+            for entry in manifest.keys {
+                let status = if manifest.active.as_deref() == Some(entry.kid.as_str()) {
+                    "active"
+                } else {
+                    "accepted"
+                };
+                println!("{}\t{}\t{}\t{}", entry.kid, entry.algorithm, status, entry.created_at);
+            }
+        }
+        ["generate"] => staged(key_store::generate(&dir, parse_algorithm("ES256")?)?),
+        ["generate", algorithm] => staged(key_store::generate(&dir, parse_algorithm(algorithm)?)?),
+        ["import", algorithm, private_key] => {
+            staged(key_store::import(&dir, parse_algorithm(algorithm)?, private_key, None)?)
+        }
+        ["import", algorithm, private_key, public_key] => staged(key_store::import(
+            &dir,
+            parse_algorithm(algorithm)?,
+            private_key,
+            Some(public_key),
+        )?),
+        ["activate", kid] => {
+            key_store::activate(&dir, kid)?;
+            println!("{} now signs new tokens once running instances reload", kid);
+        }
+        ["retire", kid] => {
+            key_store::retire(&dir, kid)?;
+            println!("tokens signed with {} are rejected once running instances reload", kid);
+        }
+        _ => usage_error(),
+    }
+    Ok(())
+}
+
+fn staged(kid: String) {
+    println!(
+        "staged {}; it is published in the JWKS after the next reload (SIGHUP or JWT_KEYS_RELOAD_SECS)",
+        kid
+    );
+    println!("activate it with `user-service keys activate {}` once consumers have refreshed their JWKS", kid);
+}
+
+fn api_keys(args: &[&str]) -> Result<(), String> {
+    let db_conn = establish_connection();
+    match args {
+        ["list"] => {
+            for key in list_api_keys(&db_conn).map_err(|e| e.to_string())? {
+                let status = if key.revoked_at.is_some() { "revoked" } else { "active" };
+                let last_used = key
+                    .last_used_at
+                    .map(|t| t.to_string())
+                    .unwrap_or_else(|| "never".to_string());
+                println!(
+                    "{}\t{}\t{}\t{}\tlast used {}",
+                    key.api_key_id,
+                    key.name,
+                    key.scopes.join(","),
+                    status,
+                    last_used
+                );
+            }
+        }
+        ["create", name, scopes] => {
+            let scopes: Vec<String> = scopes.split(',').map(|s| s.trim().to_string()).collect();
+            if let Some(unknown) = scopes.iter().find(|s| !SCOPES.contains(&s.as_str())) {
+                return Err(format!("unknown scope {}", unknown));
+            }
+            let (key, plain) = create_api_key(&db_conn, name, scopes).map_err(|e| e.to_string())?;
+            println!("created key {} for {}; send it in the X-Api-Key header:", key.api_key_id, key.name);
+            println!("{}", plain);
+        }
+        ["revoke", id] => {
+            let id: i32 = id.parse().map_err(|_| format!("invalid key id {}", id))?;
+            match revoke_api_key(&db_conn, id).map_err(|e| e.to_string())? {
+                0 => return Err(format!("no active key {}", id)),
+                _ => println!("revoked key {}", id),
+            }
+        }
+        _ => usage_error(),
+    }
+    Ok(())
+}
+
+fn read_import_file(path: &str) -> Result<Vec<ImportedUser>, String> {
+    let file = File::open(path).map_err(|e| format!("cannot open {}: {}", path, e))?;
+    if path.ends_with(".json") {
+        serde_json::from_reader(file).map_err(|e| format!("invalid JSON in {}: {}", path, e))
+    } else {
+        csv::Reader::from_reader(file)
+            .deserialize()
+            .collect::<Result<Vec<ImportedUser>, _>>()
+            .map_err(|e| format!("invalid CSV in {}: {}", path, e))
+    }
+}
+
+fn users(args: &[&str]) -> Result<(), String> {
+    match args {
+        ["import", path] => {
+            let records = read_import_file(path)?;
+            let db_conn = establish_connection();
+            let summary = import_users(&db_conn, records).map_err(|e| e.to_string())?;
+            for (login, reason) in &summary.skipped {
+                println!("skipped {}: {}", login, reason);
+            }
+            println!("imported {} users, skipped {}", summary.imported, summary.skipped.len());
+        }
+        ["grant-admin", login] => match set_system_admin(&establish_connection(), login, true) {
+            Ok(0) => return Err(format!("no user {}", login)),
+            Ok(_) => println!("{} is now a system admin", login),
+            Err(e) => return Err(e.to_string()),
+        },
+        ["revoke-admin", login] => match set_system_admin(&establish_connection(), login, false) {
+            Ok(0) => return Err(format!("no user {}", login)),
+            Ok(_) => println!("{} is no longer a system admin", login),
+            Err(e) => return Err(e.to_string()),
+        },
+        _ => usage_error(),
+    }
+    Ok(())
+}
@@ -1,9 +1,79 @@
+table! {
+    api_keys (api_key_id) {
+        api_key_id -> Int4,
+        name -> Text,
+        key_prefix -> Text,
+        key_hash -> Text,
+        scopes -> Array<Text>,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    audit_log (audit_id) {
+        audit_id -> Int4,
+        actor_id -> Nullable<Int4>,
+        subject_id -> Nullable<Int4>,
+        action -> Text,
+        details -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    guest_recovery_codes (user_id) {
+        user_id -> Int4,
+        code_hash -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    join_requests (join_request_id) {
+        join_request_id -> Int4,
+        parking_id -> Int4,
+        user_id -> Int4,
+        status -> Text,
+        created_at -> Timestamp,
+        decided_at -> Nullable<Timestamp>,
+        decided_by -> Nullable<Int4>,
+    }
+}
+
+table! {
+    oidc_identities (issuer, subject) {
+        issuer -> Text,
+        subject -> Text,
+        user_id -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    one_time_tokens (token_id) {
+        token_id -> Int4,
+        user_id -> Int4,
+        purpose -> Text,
+        token_hash -> Text,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
 table! {
     parkings (parking_id) {
         parking_id -> Int4,
         name -> Text,
         password -> Text,
         admin_id -> Int4,
+        require_verified_email -> Bool,
+        require_approval -> Bool,
+        max_members -> Nullable<Int4>,
+        allowed_email_domains -> Array<Text>,
+        public -> Bool,
     }
 }
 
@@ -11,6 +81,39 @@ table! {
     parkings_consumers (parking_id, consumer_id) {
         parking_id -> Int4,
         consumer_id -> Int4,
+        expires_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    recovery_codes (recovery_code_id) {
+        recovery_code_id -> Int4,
+        user_id -> Int4,
+        code_hash -> Text,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    sessions (session_id) {
+        session_id -> Int4,
+        user_id -> Int4,
+        created_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        device_name -> Nullable<Text>,
+        ip_address -> Nullable<Text>,
+        last_seen_at -> Timestamp,
+        impersonator_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    totp_credentials (user_id) {
+        user_id -> Int4,
+        secret -> Text,
+        confirmed_at -> Nullable<Timestamp>,
+        last_used_step -> Int8,
+        created_at -> Timestamp,
     }
 }
 
@@ -19,11 +122,54 @@ table! {
         user_id -> Int4,
         login -> Nullable<Text>,
         password -> Nullable<Text>,
+        email -> Nullable<Text>,
+        display_name -> Nullable<Text>,
+        locale -> Nullable<Text>,
+        time_zone -> Nullable<Text>,
+        guest -> Bool,
+        email_verified -> Bool,
+        system_admin -> Bool,
+        disabled_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    webauthn_credentials (credential_id) {
+        credential_id -> Text,
+        user_id -> Int4,
+        name -> Nullable<Text>,
+        public_key -> Bytea,
+        sign_count -> Int8,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
     }
 }
 
+joinable!(guest_recovery_codes -> users (user_id));
+joinable!(join_requests -> parkings (parking_id));
+joinable!(join_requests -> users (user_id));
+joinable!(oidc_identities -> users (user_id));
+joinable!(one_time_tokens -> users (user_id));
 joinable!(parkings -> users (admin_id));
 joinable!(parkings_consumers -> parkings (parking_id));
 joinable!(parkings_consumers -> users (consumer_id));
+joinable!(recovery_codes -> users (user_id));
+joinable!(sessions -> users (user_id));
+joinable!(totp_credentials -> users (user_id));
+joinable!(webauthn_credentials -> users (user_id));
 
-allow_tables_to_appear_in_same_query!(parkings, parkings_consumers, users,);
+allow_tables_to_appear_in_same_query!(
+    api_keys,
+    audit_log,
+    guest_recovery_codes,
+    join_requests,
+    oidc_identities,
+    one_time_tokens,
+    parkings,
+    parkings_consumers,
+    recovery_codes,
+    sessions,
+    totp_credentials,
+    users,
+    webauthn_credentials,
+);
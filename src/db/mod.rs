@@ -1,2 +1,8 @@
 pub mod connection;
 pub mod db_schema;
+
+/// An ILIKE pattern matching `term` anywhere, with its own wildcards taken literally.
+pub fn contains_pattern(term: &str) -> String {
+    let escaped = term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{}%", escaped)
+}
@@ -0,0 +1,80 @@
+use chrono::Utc;
+use diesel::*;
+use rand::Rng;
+
+use crate::db::db_schema::api_keys;
+use crate::handlers::error_handler::Error;
+use crate::models::api_key::ApiKey;
+use crate::security::{generate_token, hash_token};
+
+pub const SCOPE_INTROSPECT: &str = "introspect";
+pub const SCOPE_MEMBERSHIPS_READ: &str = "memberships:read";
+pub const SCOPES: &[&str] = &[SCOPE_INTROSPECT, SCOPE_MEMBERSHIPS_READ];
+
+const KEY_PREFIX: &str = "usk";
+
+/// Returns the stored key together with its plain value, which is shown once and never persisted.
+pub fn create_api_key(
+    db_conn: &PgConnection,
+    name: &str,
+    scopes: Vec<String>,
+) -> QueryResult<(ApiKey, String)> {
+    let prefix = hex::encode(rand::thread_rng().gen::<[u8; 6]>());
+    let key = format!("{}_{}_{}", KEY_PREFIX, prefix, generate_token());
+    let api_key = insert_into(api_keys::dsl::api_keys)
+        .values((
+            api_keys::dsl::name.eq(name),
+            api_keys::dsl::key_prefix.eq(&prefix),
+            api_keys::dsl::key_hash.eq(hash_token(&key)),
+            api_keys::dsl::scopes.eq(scopes),
+        ))
+        .get_result::<ApiKey>(db_conn)?;
+    Ok((api_key, key))
+}
+
+pub fn list_api_keys(db_conn: &PgConnection) -> QueryResult<Vec<ApiKey>> {
+    api_keys::dsl::api_keys
+        .order(api_keys::dsl::api_key_id.asc())
+        .load::<ApiKey>(db_conn)
+}
+
+pub fn revoke_api_key(db_conn: &PgConnection, api_key_id: i32) -> QueryResult<usize> {
+    diesel::update(
+        api_keys::dsl::api_keys
+            .find(api_key_id)
+            .filter(api_keys::dsl::revoked_at.is_null()),
+    )
+    .set(api_keys::dsl::revoked_at.eq(Some(Utc::now().naive_utc())))
+    .execute(db_conn)
+}
+
+/// Looks the key up by its prefix, compares hashes, checks the scope and records the use.
+pub fn authenticate_api_key(db_conn: &PgConnection, key: &str, scope: &str) -> Result<ApiKey, Error> {
+    let prefix = match key.split('_').collect::<Vec<&str>>().as_slice() {
+        [KEY_PREFIX, prefix, _] => prefix.to_string(),
+        _ => return Err(Error::InvalidClientError),
+    };
+    let api_key = api_keys::dsl::api_keys
+        .filter(api_keys::dsl::key_prefix.eq(prefix))
+        .filter(api_keys::dsl::revoked_at.is_null())
+        .first::<ApiKey>(db_conn)
+        .map_err(|_| Error::InvalidClientError)?;
+    if ring::constant_time::verify_slices_are_equal(
+        api_key.key_hash.as_bytes(),
+        hash_token(key).as_bytes(),
+    )
+    .is_err()
+    {
+        return Err(Error::InvalidClientError);
+    }
+    if !api_key.has_scope(scope) {
+        return Err(Error::InsufficientScopeError);
+    }
+    let used = diesel::update(api_keys::dsl::api_keys.find(api_key.api_key_id))
+        .set(api_keys::dsl::last_used_at.eq(Some(Utc::now().naive_utc())))
+        .execute(db_conn);
+    if let Err(e) = used {
+        eprintln!("api key {} usage not recorded: {:?}", api_key.api_key_id, e);
+    }
+    Ok(api_key)
+}
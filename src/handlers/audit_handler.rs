@@ -0,0 +1,67 @@
+use diesel::*;
+
+use crate::db::db_schema::audit_log;
+use crate::models::audit_entry::AuditEntry;
+
+pub const PASSWORD_CHANGED: &str = "password_changed";
+pub const PASSWORD_RESET: &str = "password_reset";
+pub const ACCOUNT_DELETED: &str = "account_deleted";
+pub const PARKING_TRANSFERRED: &str = "parking_transferred";
+pub const OIDC_LINKED: &str = "oidc_linked";
+pub const OIDC_LOGIN: &str = "oidc_login";
+pub const MFA_ENABLED: &str = "mfa_enabled";
+pub const MFA_DISABLED: &str = "mfa_disabled";
+pub const RECOVERY_CODES_REGENERATED: &str = "recovery_codes_regenerated";
+pub const PASSKEY_ADDED: &str = "passkey_added";
+pub const PASSKEY_REMOVED: &str = "passkey_removed";
+pub const SESSION_REVOKED: &str = "session_revoked";
+pub const USER_IMPORTED: &str = "user_imported";
+pub const EMAIL_VERIFIED: &str = "email_verified";
+pub const MAGIC_LINK_SENT: &str = "magic_link_sent";
+pub const MAGIC_LINK_LOGIN: &str = "magic_link_login";
+pub const GUEST_RECOVERED: &str = "guest_recovered";
+pub const DEVICE_PAIRED: &str = "device_paired";
+pub const ACCOUNT_DISABLED: &str = "account_disabled";
+pub const ACCOUNT_ENABLED: &str = "account_enabled";
+pub const PASSWORD_RESET_FORCED: &str = "password_reset_forced";
+pub const PARKING_REASSIGNED: &str = "parking_reassigned";
+pub const SYSTEM_ADMIN_GRANTED: &str = "system_admin_granted";
+pub const SYSTEM_ADMIN_REVOKED: &str = "system_admin_revoked";
+pub const IMPERSONATION_STARTED: &str = "impersonation_started";
+pub const IMPERSONATED_REQUEST: &str = "impersonated_request";
+pub const JOIN_REQUEST_APPROVED: &str = "join_request_approved";
+pub const JOIN_REQUEST_REJECTED: &str = "join_request_rejected";
+pub const MEMBERSHIP_EXPIRY_SET: &str = "membership_expiry_set";
+pub const MEMBERSHIP_EXPIRED: &str = "membership_expired";
+
+/// Audit failures are logged rather than propagated so they never block the audited action.
+pub fn record_event(
+    db_conn: &PgConnection,
+    actor_id: Option<i32>,
+    subject_id: Option<i32>,
+    action: &str,
+    details: Option<String>,
+) {
+    let result = insert_into(audit_log::dsl::audit_log)
+        .values((
+            audit_log::dsl::actor_id.eq(actor_id),
+            audit_log::dsl::subject_id.eq(subject_id),
+            audit_log::dsl::action.eq(action),
+            audit_log::dsl::details.eq(details),
+        ))
+        .execute(db_conn);
+    if let Err(e) = result {
+        eprintln!("audit event {} not recorded: {:?}", action, e);
+    }
+}
+
+pub fn events_for_user(db_conn: &PgConnection, user_id: i32) -> QueryResult<Vec<AuditEntry>> {
+    audit_log::dsl::audit_log
+        .filter(
+            audit_log::dsl::actor_id
+                .eq(user_id)
+                .or(audit_log::dsl::subject_id.eq(user_id)),
+        )
+        .order(audit_log::dsl::created_at.asc())
+        .load::<AuditEntry>(db_conn)
+}
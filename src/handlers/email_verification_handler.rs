@@ -0,0 +1,95 @@
+use chrono::Duration;
+use std::env;
+use std::ops::Deref;
+use warp::{http::StatusCode, reject, Rejection, Reply};
+
+use crate::db::db_schema::users;
+use crate::handlers::audit_handler::{record_event, EMAIL_VERIFIED};
+use crate::handlers::error_handler;
+use crate::handlers::one_time_token_handler::{
+    consume_token, invalidate_tokens, issue_token, EMAIL_VERIFICATION,
+};
+use crate::mail::{send_in_background, Email, SharedMailer};
+use crate::models::user::User;
+use crate::routes::Db;
+use diesel::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+fn verification_token_ttl() -> Duration {
+    let hours = env::var("EMAIL_VERIFICATION_TTL_HOURS")
+        .ok()
+        .and_then(|h| h.parse().ok())
+        .unwrap_or(48);
+    Duration::hours(hours)
+}
+
+fn verification_link(token: &str) -> String {
+    let base = env::var("EMAIL_VERIFICATION_URL").unwrap_or_else(|_| "/email/verify".to_string());
+    format!("{}?token={}", base, token)
+}
+
+/// Replaces any earlier verification link, so only the one sent to the current address works.
+/// The mail goes out in the background and failures are only logged; the user can ask for
+/// another link.
+pub fn send_verification_email(
+    db_conn: &PgConnection,
+    mailer: &SharedMailer,
+    user_id: i32,
+    address: &str,
+) -> QueryResult<()> {
+    invalidate_tokens(db_conn, user_id, EMAIL_VERIFICATION)?;
+    let token = issue_token(db_conn, user_id, EMAIL_VERIFICATION, verification_token_ttl())?;
+    let email = Email {
+        to: address.to_string(),
+        subject: "Confirm your email address".to_string(),
+        body: format!(
+            "Open {} to confirm that this address belongs to you. If you did not add it to an account, ignore this message.",
+            verification_link(&token)
+        ),
+    };
+    send_in_background(mailer, email, format!("verification mail for user {}", user_id));
+    Ok(())
+}
+
+pub async fn request_verification(
+    db: Db,
+    mailer: SharedMailer,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    let user = users::dsl::users
+        .find(user_id)
+        .first::<User>(db_conn)
+        .map_err(|_| reject::custom(error_handler::Error::NoPermissionError))?;
+    match user.email {
+        None => Err(reject::custom(error_handler::Error::NoEmailError)),
+        Some(_) if user.email_verified => Ok(StatusCode::NO_CONTENT),
+        Some(address) => {
+            send_verification_email(db_conn, &mailer, user_id, &address).map_err(|_| reject::reject())?;
+            Ok(StatusCode::ACCEPTED)
+        }
+    }
+}
+
+pub async fn verify_email(body: VerifyEmailRequest, db: Db) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let user_id = consume_token(db_conn, EMAIL_VERIFICATION, &body.token)
+        .ok_or_else(|| reject::custom(error_handler::Error::InvalidTokenError))?;
+    diesel::update(users::dsl::users.find(user_id).filter(users::dsl::email.is_not_null()))
+        .set(users::dsl::email_verified.eq(true))
+        .execute(db_conn)
+        .map_err(|_| reject::reject())?;
+    record_event(db_conn, Some(user_id), Some(user_id), EMAIL_VERIFIED, None);
+    Ok(StatusCode::OK)
+}
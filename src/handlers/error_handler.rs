@@ -1,7 +1,8 @@
 use serde::Serialize;
 use std::convert::Infallible;
 use thiserror::Error;
-use warp::http::StatusCode;
+use warp::http::header::RETRY_AFTER;
+use warp::http::{HeaderValue, StatusCode};
 use warp::{Rejection, Reply};
 
 #[derive(Error, Debug)]
@@ -22,6 +23,54 @@ pub enum Error {
     LoginInUseError,
     #[error("no permission")]
     NoPermissionError,
+    #[error("token is invalid or expired")]
+    InvalidTokenError,
+    #[error("This email is taken. Try another.")]
+    EmailInUseError,
+    #[error("enter a valid email address")]
+    InvalidEmailError,
+    #[error("unknown locale")]
+    InvalidLocaleError,
+    #[error("unknown time zone")]
+    InvalidTimeZoneError,
+    #[error("too many attempts, try again later")]
+    TooManyAttemptsError(u64),
+    #[error("transfer or delete the parkings you administer first")]
+    OwnedParkingsError,
+    #[error("invalid client credentials")]
+    InvalidClientError,
+    #[error("credentials lack the required scope")]
+    InsufficientScopeError,
+    #[error("single sign-on is not configured")]
+    OidcNotConfiguredError,
+    #[error("single sign-on failed")]
+    OidcLoginError,
+    #[error("two-factor authentication is already enabled")]
+    MfaAlreadyEnabledError,
+    #[error("invalid verification code")]
+    InvalidMfaCodeError,
+    #[error("passkey could not be verified")]
+    InvalidPasskeyError,
+    #[error("add an email address first")]
+    NoEmailError,
+    #[error("a verified email address is required to join this parking")]
+    EmailVerificationRequiredError,
+    #[error("missing or invalid CSRF token")]
+    CsrfError,
+    #[error("this account is disabled")]
+    AccountDisabledError,
+    #[error("this token is read-only")]
+    ReadOnlyTokenError,
+    #[error("not allowed while impersonating")]
+    ImpersonationError,
+    #[error("this parking has no free places")]
+    ParkingFullError,
+    #[error("register with your work email address to join this parking")]
+    RegistrationRequiredError,
+    #[error("sign in to ask to join this parking")]
+    SignInRequiredError,
+    #[error("your email domain is not allowed to join this parking")]
+    EmailDomainNotAllowedError,
 }
 
 #[derive(Serialize, Debug)]
@@ -46,6 +95,30 @@ pub async fn handle_rejection(err: Rejection) -> std::result::Result<impl Reply,
             Error::WrongParkingError => (StatusCode::BAD_REQUEST, error.to_string()),
             Error::LoginInUseError => (StatusCode::BAD_REQUEST, error.to_string()),
             Error::NoPermissionError => (StatusCode::UNAUTHORIZED, error.to_string()),
+            Error::InvalidTokenError => (StatusCode::BAD_REQUEST, error.to_string()),
+            Error::EmailInUseError => (StatusCode::BAD_REQUEST, error.to_string()),
+            Error::InvalidEmailError => (StatusCode::BAD_REQUEST, error.to_string()),
+            Error::InvalidLocaleError => (StatusCode::BAD_REQUEST, error.to_string()),
+            Error::InvalidTimeZoneError => (StatusCode::BAD_REQUEST, error.to_string()),
+            Error::TooManyAttemptsError(_) => (StatusCode::TOO_MANY_REQUESTS, error.to_string()),
+            Error::OwnedParkingsError => (StatusCode::CONFLICT, error.to_string()),
+            Error::InvalidClientError => (StatusCode::UNAUTHORIZED, error.to_string()),
+            Error::InsufficientScopeError => (StatusCode::FORBIDDEN, error.to_string()),
+            Error::OidcNotConfiguredError => (StatusCode::NOT_FOUND, error.to_string()),
+            Error::OidcLoginError => (StatusCode::BAD_REQUEST, error.to_string()),
+            Error::MfaAlreadyEnabledError => (StatusCode::CONFLICT, error.to_string()),
+            Error::InvalidMfaCodeError => (StatusCode::FORBIDDEN, error.to_string()),
+            Error::InvalidPasskeyError => (StatusCode::BAD_REQUEST, error.to_string()),
+            Error::NoEmailError => (StatusCode::BAD_REQUEST, error.to_string()),
+            Error::EmailVerificationRequiredError => (StatusCode::FORBIDDEN, error.to_string()),
+            Error::CsrfError => (StatusCode::FORBIDDEN, error.to_string()),
+            Error::AccountDisabledError => (StatusCode::FORBIDDEN, error.to_string()),
+            Error::ReadOnlyTokenError => (StatusCode::FORBIDDEN, error.to_string()),
+            Error::ImpersonationError => (StatusCode::FORBIDDEN, error.to_string()),
+            Error::ParkingFullError => (StatusCode::CONFLICT, error.to_string()),
+            Error::RegistrationRequiredError => (StatusCode::FORBIDDEN, error.to_string()),
+            Error::SignInRequiredError => (StatusCode::UNAUTHORIZED, error.to_string()),
+            Error::EmailDomainNotAllowedError => (StatusCode::FORBIDDEN, error.to_string()),
             _ => (StatusCode::BAD_REQUEST, error.to_string()),
         }
     } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
@@ -65,5 +138,11 @@ pub async fn handle_rejection(err: Rejection) -> std::result::Result<impl Reply,
         status: code.to_string(),
         message,
     });
-    Ok(warp::reply::with_status(json, code))
+    let mut response = warp::reply::with_status(json, code).into_response();
+    if let Some(Error::TooManyAttemptsError(retry_after)) = err.find::<Error>() {
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(*retry_after));
+    }
+    Ok(response)
 }
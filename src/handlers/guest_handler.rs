@@ -0,0 +1,232 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use std::ops::Deref;
+use warp::reply::Response;
+use warp::{reject, reply, Rejection, Reply};
+
+use crate::db::db_schema::{guest_recovery_codes, users};
+use crate::handlers::audit_handler::{record_event, DEVICE_PAIRED, GUEST_RECOVERED};
+use crate::handlers::error_handler;
+use crate::handlers::one_time_token_handler::{consume_token_with_id, store_token, DEVICE_PAIRING};
+use crate::handlers::session_handler::open_session;
+use crate::handlers::user_handler::{finish_first_factor, login_reply, login_reply_with};
+use crate::models::session::ClientInfo;
+use crate::routes::Db;
+use crate::security::throttle::{AttemptKey, SharedThrottle};
+use crate::security::{generate_code, hash_token, normalize_code};
+use diesel::*;
+use serde::{Deserialize, Serialize};
+
+/// 24 characters from a 31 letter alphabet, about 118 bits, since the code alone identifies the account.
+const RECOVERY_CODE_LENGTH: usize = 24;
+/// Pairing codes are short-lived and throttled, so they can be short enough to type.
+const PAIRING_CODE_LENGTH: usize = 8;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryCodeResponse {
+    pub recovery_code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoverGuestRequest {
+    pub recovery_code: String,
+    /// Browser clients ask for the session in a cookie instead of the response body.
+    #[serde(default)]
+    pub cookie: bool,
+}
+
+/// Added to the login response of a recovered guest.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoverGuestResponse {
+    /// The code used for recovery is replaced by this one.
+    pub recovery_code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PairingCodeResponse {
+    /// The number of the code, a dash and the code itself, e.g. `1234-ABCD-EFGH`.
+    pub pairing_code: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PairDeviceRequest {
+    pub pairing_code: String,
+    /// Browser clients ask for the session in a cookie instead of the response body.
+    #[serde(default)]
+    pub cookie: bool,
+}
+
+fn pairing_code_ttl() -> Duration {
+    Duration::minutes(5)
+}
+
+/// Replaces the account's recovery code and returns the new one; only its hash is stored.
+pub fn issue_recovery_code(db_conn: &PgConnection, user_id: i32) -> QueryResult<String> {
+    let code = generate_code(RECOVERY_CODE_LENGTH);
+    diesel::delete(guest_recovery_codes::dsl::guest_recovery_codes.find(user_id)).execute(db_conn)?;
+    insert_into(guest_recovery_codes::dsl::guest_recovery_codes)
+        .values((
+            guest_recovery_codes::dsl::user_id.eq(user_id),
+            guest_recovery_codes::dsl::code_hash.eq(hash_token(&normalize_code(&code))),
+        ))
+        .execute(db_conn)?;
+    Ok(code)
+}
+
+/// Called once the account has a password or passkey, so the code no longer signs in.
+pub fn forget_recovery_code(db_conn: &PgConnection, user_id: i32) -> QueryResult<usize> {
+    diesel::delete(guest_recovery_codes::dsl::guest_recovery_codes.find(user_id)).execute(db_conn)
+}
+
+fn is_guest(db_conn: &PgConnection, user_id: i32) -> QueryResult<bool> {
+    users::dsl::users.find(user_id).select(users::dsl::guest).first(db_conn)
+}
+
+/// Without a known address the request is refused rather than left unthrottled.
+fn ip_attempt_keys(client: &ClientInfo) -> Result<Vec<AttemptKey>, Rejection> {
+    match client.ip {
+        Some(ip) => Ok(vec![AttemptKey::Ip(ip)]),
+        None => Err(reject::custom(error_handler::Error::NoPermissionError)),
+    }
+}
+
+/// Splits a typed pairing code into its number and the code itself.
+fn parse_pairing_code(pairing_code: &str) -> Option<(i32, String)> {
+    let (token_id, code) = pairing_code.trim().split_once(|c: char| !c.is_ascii_digit())?;
+    Some((token_id.parse().ok()?, normalize_code(code)))
+}
+
+/// Keeps the parts of a recovery that must not happen without each other in one transaction.
+enum RecoveryError {
+    Query,
+    Refused(Rejection),
+}
+
+impl From<diesel::result::Error> for RecoveryError {
+    fn from(_: diesel::result::Error) -> Self {
+        RecoveryError::Query
+    }
+}
+
+/// Recovery and pairing codes stand in for credentials, so only guest accounts get them.
+fn guest_user_id(db_conn: &PgConnection, user_id: Option<i32>) -> Result<i32, Rejection> {
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+    if !is_guest(db_conn, user_id).map_err(|_| reject::reject())? {
+        return Err(reject::custom(error_handler::Error::NoPermissionError));
+    }
+    Ok(user_id)
+}
+
+pub async fn rotate_recovery_code(db: Db, user_id: Option<i32>) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let user_id = guest_user_id(db_conn, user_id)?;
+
+    let recovery_code = issue_recovery_code(db_conn, user_id).map_err(|_| reject::reject())?;
+    Ok(reply::json(&RecoveryCodeResponse { recovery_code }))
+}
+
+/// Signs a reinstalled app back into its guest account. The code is single use:
+/// a fresh one is returned with the token. If the session cannot be opened, e.g. because
+/// the account is disabled, the old code stays valid.
+pub async fn recover_guest(
+    body: RecoverGuestRequest,
+    db: Db,
+    throttle: SharedThrottle,
+    client: ClientInfo,
+) -> Result<Response, Rejection> {
+    let attempt_keys = ip_attempt_keys(&client)?;
+    throttle
+        .check(&attempt_keys)
+        .map_err(|retry_after| reject::custom(error_handler::Error::TooManyAttemptsError(retry_after)))?;
+
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let recovered = db_conn.transaction::<_, RecoveryError, _>(|| {
+        let user_id = diesel::delete(
+            guest_recovery_codes::dsl::guest_recovery_codes
+                .filter(guest_recovery_codes::dsl::code_hash.eq(hash_token(&normalize_code(&body.recovery_code)))),
+        )
+        .returning(guest_recovery_codes::dsl::user_id)
+        .get_result::<i32>(db_conn)
+        .optional()?;
+        let user_id = match user_id {
+            Some(user_id) => user_id,
+            None => return Ok(None),
+        };
+        if !is_guest(db_conn, user_id)? {
+            // a code left over from before the account was upgraded; its second factor still applies
+            let response = finish_first_factor(db_conn, user_id, &client, body.cookie).map_err(RecoveryError::Refused)?;
+            return Ok(Some((user_id, response)));
+        }
+        let recovery_code = issue_recovery_code(db_conn, user_id)?;
+        let token = open_session(db_conn, user_id, &client).map_err(|e| RecoveryError::Refused(reject::custom(e)))?;
+        Ok(Some((user_id, login_reply_with(token, body.cookie, &RecoverGuestResponse { recovery_code }))))
+    });
+    let (user_id, response) = match recovered {
+        Ok(Some(recovered)) => recovered,
+        Ok(None) => {
+            throttle.record_failure(&attempt_keys);
+            return Err(reject::custom(error_handler::Error::InvalidTokenError));
+        }
+        Err(RecoveryError::Query) => return Err(reject::reject()),
+        Err(RecoveryError::Refused(rejection)) => return Err(rejection),
+    };
+    record_event(db_conn, Some(user_id), Some(user_id), GUEST_RECOVERED, client.ip.map(|ip| ip.to_string()));
+    Ok(response)
+}
+
+/// Shown on a signed-in device so a second one can join the same account.
+pub async fn create_pairing_code(db: Db, user_id: Option<i32>) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let user_id = guest_user_id(db_conn, user_id)?;
+
+    let code = generate_code(PAIRING_CODE_LENGTH);
+    let token_id = store_token(db_conn, user_id, DEVICE_PAIRING, &normalize_code(&code), pairing_code_ttl())
+        .map_err(|_| reject::reject())?;
+    Ok(reply::json(&PairingCodeResponse {
+        pairing_code: format!("{}-{}", token_id, code),
+        expires_at: Utc::now().naive_utc() + pairing_code_ttl(),
+    }))
+}
+
+pub async fn pair_device(
+    body: PairDeviceRequest,
+    db: Db,
+    throttle: SharedThrottle,
+    client: ClientInfo,
+) -> Result<Response, Rejection> {
+    // the number in front of the code lets wrong guesses count against that one code
+    let mut attempt_keys = ip_attempt_keys(&client)?;
+    let pairing_code = parse_pairing_code(&body.pairing_code);
+    attempt_keys.extend(pairing_code.as_ref().map(|(token_id, _)| AttemptKey::Pairing(*token_id)));
+    throttle
+        .check(&attempt_keys)
+        .map_err(|retry_after| reject::custom(error_handler::Error::TooManyAttemptsError(retry_after)))?;
+
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let user_id = pairing_code
+        .and_then(|(token_id, code)| consume_token_with_id(db_conn, DEVICE_PAIRING, token_id, &code));
+    let user_id = match user_id {
+        Some(user_id) => user_id,
+        None => {
+            throttle.record_failure(&attempt_keys);
+            return Err(reject::custom(error_handler::Error::InvalidTokenError));
+        }
+    };
+    record_event(db_conn, Some(user_id), Some(user_id), DEVICE_PAIRED, client.device_name());
+    if !is_guest(db_conn, user_id).map_err(|_| reject::reject())? {
+        return finish_first_factor(db_conn, user_id, &client, body.cookie);
+    }
+    let token = open_session(db_conn, user_id, &client).map_err(reject::custom)?;
+    Ok(login_reply(token, body.cookie))
+}
@@ -0,0 +1,81 @@
+use std::ops::Deref;
+use warp::{reject, reply, Rejection, Reply};
+
+use crate::db::db_schema::users;
+use crate::handlers::parking_handler::get_memberships;
+use crate::handlers::session_handler::validate_token;
+use crate::models::parking::Membership;
+use crate::models::user::User;
+use crate::routes::Db;
+use diesel::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IntrospectionRequest {
+    pub token: String,
+    pub token_type_hint: Option<String>,
+}
+
+/// RFC 7662 response; inactive tokens carry no other fields.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guest: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memberships: Option<Vec<Membership>>,
+    /// RFC 8693 actor of an impersonation token.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Actor {
+    pub sub: String,
+}
+
+pub async fn introspect(
+    body: IntrospectionRequest,
+    db: Db,
+    _client_id: String,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let claims = match validate_token(db_conn, &body.token) {
+        Ok(claims) => claims,
+        Err(_) => return Ok(reply::json(&IntrospectionResponse::default())),
+    };
+    let user = match users::dsl::users.find(claims.id).first::<User>(db_conn) {
+        Ok(user) => user,
+        Err(_) => return Ok(reply::json(&IntrospectionResponse::default())),
+    };
+    let memberships = get_memberships(db_conn, user.id).map_err(|_| reject::reject())?;
+
+    Ok(reply::json(&IntrospectionResponse {
+        active: true,
+        sub: Some(user.id.to_string()),
+        exp: Some(claims.exp),
+        token_type: Some("Bearer".to_string()),
+        guest: Some(user.is_guest()),
+        username: user.login,
+        sid: Some(claims.sid),
+        memberships: Some(memberships),
+        act: claims.actor_id.map(|actor_id| Actor {
+            sub: actor_id.to_string(),
+        }),
+        read_only: Some(claims.read_only).filter(|read_only| *read_only),
+    }))
+}
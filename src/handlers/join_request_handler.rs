@@ -0,0 +1,201 @@
+use chrono::Utc;
+use std::ops::Deref;
+use warp::{reject, reply, Rejection, Reply};
+
+use crate::db::db_schema::{join_requests, users};
+use crate::handlers::audit_handler::{record_event, JOIN_REQUEST_APPROVED, JOIN_REQUEST_REJECTED};
+use crate::handlers::error_handler;
+use crate::handlers::membership_handler::{add_member, ensure_capacity, is_member};
+use crate::handlers::parking_settings_handler::administered_parking;
+use crate::mail::{send_in_background, Email, SharedMailer};
+use crate::models::join_request::{JoinRequest, PendingJoinRequest, APPROVED, PENDING, REJECTED};
+use crate::models::parking::Parking;
+use crate::models::user::User;
+use crate::routes::Db;
+use diesel::*;
+
+fn notify(mailer: &SharedMailer, user: &User, subject: String, body: String) {
+    if let Some(address) = &user.email {
+        let email = Email {
+            to: address.clone(),
+            subject,
+            body,
+        };
+        send_in_background(mailer, email, format!("join request mail for user {}", user.id));
+    }
+}
+
+fn describe(user: &User) -> String {
+    user.display_name
+        .clone()
+        .or_else(|| user.login.clone())
+        .unwrap_or_else(|| format!("A guest (user {})", user.id))
+}
+
+/// Files a join request for a parking in approval mode and mails its admin. Asking again while
+/// a request is pending returns that request.
+pub fn request_to_join(
+    db_conn: &PgConnection,
+    mailer: &SharedMailer,
+    parking: &Parking,
+    user_id: i32,
+) -> QueryResult<JoinRequest> {
+    let pending = join_requests::dsl::join_requests
+        .filter(join_requests::dsl::parking_id.eq(parking.parking_id))
+        .filter(join_requests::dsl::user_id.eq(user_id))
+        .filter(join_requests::dsl::status.eq(PENDING))
+        .first::<JoinRequest>(db_conn)
+        .optional()?;
+    if let Some(request) = pending {
+        return Ok(request);
+    }
+    let request = insert_into(join_requests::dsl::join_requests)
+        .values((
+            join_requests::dsl::parking_id.eq(parking.parking_id),
+            join_requests::dsl::user_id.eq(user_id),
+        ))
+        .get_result::<JoinRequest>(db_conn)?;
+
+    let requester = users::dsl::users.find(user_id).first::<User>(db_conn)?;
+    let admin = users::dsl::users.find(parking.admin_id).first::<User>(db_conn)?;
+    notify(
+        mailer,
+        &admin,
+        format!("New request to join {}", parking.name),
+        format!(
+            "{} asked to join {}. Approve or reject the request in the app.",
+            describe(&requester),
+            parking.name
+        ),
+    );
+    Ok(request)
+}
+
+pub async fn list_join_requests(
+    parking_id: i32,
+    db: Db,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    administered_parking(db_conn, parking_id, user_id)?;
+    let requests = join_requests::dsl::join_requests
+        .inner_join(users::dsl::users)
+        .filter(join_requests::dsl::parking_id.eq(parking_id))
+        .filter(join_requests::dsl::status.eq(PENDING))
+        .order(join_requests::dsl::created_at)
+        .select((
+            join_requests::dsl::join_request_id,
+            join_requests::dsl::user_id,
+            users::dsl::login,
+            users::dsl::display_name,
+            users::dsl::email,
+            users::dsl::guest,
+            join_requests::dsl::created_at,
+        ))
+        .load::<PendingJoinRequest>(db_conn)
+        .map_err(|_| reject::reject())?;
+    Ok(reply::json(&requests))
+}
+
+pub fn join_requests_for_user(db_conn: &PgConnection, user_id: i32) -> QueryResult<Vec<JoinRequest>> {
+    join_requests::dsl::join_requests
+        .filter(join_requests::dsl::user_id.eq(user_id))
+        .order(join_requests::dsl::created_at.desc())
+        .load::<JoinRequest>(db_conn)
+}
+
+/// Lets requesters, guests without an email address in particular, follow their requests.
+pub async fn list_own_join_requests(db: Db, user_id: Option<i32>) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    let requests = join_requests_for_user(db_conn, user_id).map_err(|_| reject::reject())?;
+    Ok(reply::json(&requests))
+}
+
+/// Records the admin's decision, adds the requester to the parking when approved and mails them.
+fn decide(
+    db_conn: &PgConnection,
+    mailer: &SharedMailer,
+    parking_id: i32,
+    join_request_id: i32,
+    user_id: Option<i32>,
+    approve: bool,
+) -> Result<JoinRequest, Rejection> {
+    let parking = administered_parking(db_conn, parking_id, user_id)?;
+    let request = join_requests::dsl::join_requests
+        .find(join_request_id)
+        .filter(join_requests::dsl::parking_id.eq(parking_id))
+        .first::<JoinRequest>(db_conn)
+        .map_err(|_| reject::not_found())?;
+    if !request.is_pending() {
+        return Err(reject::not_found());
+    }
+    if approve && !is_member(db_conn, parking_id, request.user_id) {
+        ensure_capacity(db_conn, &parking)?;
+    }
+
+    let request = db_conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            if approve {
+                add_member(db_conn, parking_id, request.user_id)?;
+            }
+            diesel::update(join_requests::dsl::join_requests.find(join_request_id))
+                .set((
+                    join_requests::dsl::status.eq(if approve { APPROVED } else { REJECTED }),
+                    join_requests::dsl::decided_at.eq(Some(Utc::now().naive_utc())),
+                    join_requests::dsl::decided_by.eq(Some(parking.admin_id)),
+                ))
+                .get_result::<JoinRequest>(db_conn)
+        })
+        .map_err(|_| reject::reject())?;
+
+    record_event(
+        db_conn,
+        Some(parking.admin_id),
+        Some(request.user_id),
+        if approve { JOIN_REQUEST_APPROVED } else { JOIN_REQUEST_REJECTED },
+        Some(format!("parking {}", parking_id)),
+    );
+    if let Ok(requester) = users::dsl::users.find(request.user_id).first::<User>(db_conn) {
+        let outcome = if approve { "approved" } else { "declined" };
+        notify(
+            mailer,
+            &requester,
+            format!("Your request to join {} was {}", parking.name, outcome),
+            format!("The admin of {} {} your request to join.", parking.name, outcome),
+        );
+    }
+    Ok(request)
+}
+
+pub async fn approve_join_request(
+    parking_id: i32,
+    join_request_id: i32,
+    db: Db,
+    mailer: SharedMailer,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let request = decide(db_conn, &mailer, parking_id, join_request_id, user_id, true)?;
+    Ok(reply::json(&request))
+}
+
+pub async fn reject_join_request(
+    parking_id: i32,
+    join_request_id: i32,
+    db: Db,
+    mailer: SharedMailer,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let request = decide(db_conn, &mailer, parking_id, join_request_id, user_id, false)?;
+    Ok(reply::json(&request))
+}
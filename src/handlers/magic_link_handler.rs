@@ -0,0 +1,111 @@
+use chrono::Duration;
+use std::env;
+use std::ops::Deref;
+use warp::{http::StatusCode, reject, Rejection, Reply};
+
+use crate::db::db_schema::users;
+use crate::handlers::audit_handler::{record_event, MAGIC_LINK_LOGIN, MAGIC_LINK_SENT};
+use crate::handlers::error_handler;
+use crate::handlers::one_time_token_handler::{
+    consume_token, invalidate_tokens, issue_token, MAGIC_LINK,
+};
+use crate::handlers::user_handler::finish_first_factor;
+use crate::mail::{send_in_background, Email, SharedMailer};
+use crate::models::session::ClientInfo;
+use crate::models::user::User;
+use crate::routes::Db;
+use crate::security::throttle::{AttemptKey, SharedThrottle};
+use diesel::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MagicLinkRequest {
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MagicLinkExchangeRequest {
+    pub token: String,
+    #[serde(default)]
+    pub cookie: bool,
+}
+
+fn magic_link_ttl() -> Duration {
+    let minutes = env::var("MAGIC_LINK_TTL_MINUTES")
+        .ok()
+        .and_then(|m| m.parse().ok())
+        .unwrap_or(15);
+    Duration::minutes(minutes)
+}
+
+fn magic_link(token: &str) -> String {
+    let base = env::var("MAGIC_LINK_URL").unwrap_or_else(|_| "/login/magic-link".to_string());
+    format!("{}?token={}", base, token)
+}
+
+/// Always answers 202 so the response does not reveal which addresses are registered.
+/// Links only go to verified addresses, and every request counts against the throttle
+/// so the endpoint cannot be used to flood a mailbox.
+pub async fn request_magic_link(
+    body: MagicLinkRequest,
+    db: Db,
+    mailer: SharedMailer,
+    throttle: SharedThrottle,
+    client: ClientInfo,
+) -> Result<impl Reply, Rejection> {
+    // stored addresses are lowercase, see `normalize_email`
+    let address = body.email.trim().to_lowercase();
+    let attempt_keys = AttemptKey::MagicLink(address.clone()).with_ip(client.ip);
+    throttle
+        .check(&attempt_keys)
+        .map_err(|retry_after| reject::custom(error_handler::Error::TooManyAttemptsError(retry_after)))?;
+    throttle.record_failure(&attempt_keys);
+
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let user = users::dsl::users
+        .filter(users::dsl::email.eq(&address))
+        .filter(users::dsl::email_verified.eq(true))
+        .first::<User>(db_conn);
+    if let Ok(user) = user {
+        invalidate_tokens(db_conn, user.id, MAGIC_LINK).map_err(|_| reject::reject())?;
+        let token = issue_token(db_conn, user.id, MAGIC_LINK, magic_link_ttl()).map_err(|_| reject::reject())?;
+        let email = Email {
+            to: address,
+            subject: "Your sign-in link".to_string(),
+            body: format!(
+                "Open {} to sign in. The link works once and expires in {} minutes. If you did not ask for it, ignore this message.",
+                magic_link(&token),
+                magic_link_ttl().num_minutes()
+            ),
+        };
+        // sent off the request so known and unknown addresses answer equally fast
+        send_in_background(&mailer, email, format!("magic link mail for user {}", user.id));
+        record_event(db_conn, None, Some(user.id), MAGIC_LINK_SENT, client.ip.map(|ip| ip.to_string()));
+    }
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Trades a magic link token for the usual login response, including the MFA step when enabled.
+pub async fn exchange_magic_link(
+    body: MagicLinkExchangeRequest,
+    db: Db,
+    client: ClientInfo,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let user_id = consume_token(db_conn, MAGIC_LINK, &body.token)
+        .ok_or_else(|| reject::custom(error_handler::Error::InvalidTokenError))?;
+    record_event(
+        db_conn,
+        Some(user_id),
+        Some(user_id),
+        MAGIC_LINK_LOGIN,
+        client.ip.map(|ip| ip.to_string()),
+    );
+    finish_first_factor(db_conn, user_id, &client, body.cookie)
+}
@@ -0,0 +1,158 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::*;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::ops::Deref;
+use std::time::Duration;
+use warp::{reject, reply, Rejection, Reply};
+
+use crate::db::db_schema::parkings_consumers;
+use crate::handlers::audit_handler::{record_event, MEMBERSHIP_EXPIRED, MEMBERSHIP_EXPIRY_SET};
+use crate::handlers::error_handler;
+use crate::handlers::parking_settings_handler::administered_parking;
+use crate::models::parking::Parking;
+use crate::models::parking_consumer::ParkingConsumer;
+use crate::routes::Db;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MembershipExpiryRequest {
+    /// A missing or null date keeps the membership indefinitely.
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// Memberships of the parking that have not expired; expired rows wait for the cleanup task.
+pub fn active_members(db_conn: &PgConnection, parking_id: i32) -> QueryResult<Vec<ParkingConsumer>> {
+    parkings_consumers::dsl::parkings_consumers
+        .filter(parkings_consumers::dsl::parking_id.eq(parking_id))
+        .filter(
+            parkings_consumers::dsl::expires_at
+                .is_null()
+                .or(parkings_consumers::dsl::expires_at.gt(Utc::now().naive_utc())),
+        )
+        .load::<ParkingConsumer>(db_conn)
+}
+
+/// Every membership of the user, expiry dates included.
+pub fn memberships_for_user(db_conn: &PgConnection, user_id: i32) -> QueryResult<Vec<ParkingConsumer>> {
+    parkings_consumers::dsl::parkings_consumers
+        .filter(parkings_consumers::dsl::consumer_id.eq(user_id))
+        .order(parkings_consumers::dsl::parking_id.asc())
+        .load::<ParkingConsumer>(db_conn)
+}
+
+pub fn is_member(db_conn: &PgConnection, parking_id: i32, user_id: i32) -> bool {
+    parkings_consumers::dsl::parkings_consumers
+        .find((parking_id, user_id))
+        .first::<ParkingConsumer>(db_conn)
+        .map(|membership| match membership.expires_at {
+            Some(at) => at > Utc::now().naive_utc(),
+            None => true,
+        })
+        .unwrap_or(false)
+}
+
+/// Refuses new members once the parking has `max_members` active ones.
+pub fn ensure_capacity(db_conn: &PgConnection, parking: &Parking) -> Result<(), Rejection> {
+    if let Some(max_members) = parking.max_members {
+        let members = active_members(db_conn, parking.parking_id).map_err(|_| reject::reject())?;
+        if members.len() as i64 >= max_members as i64 {
+            return Err(reject::custom(error_handler::Error::ParkingFullError));
+        }
+    }
+    Ok(())
+}
+
+/// Adds a consumer; joining again after an expiry starts a membership without an end date.
+pub fn add_member(db_conn: &PgConnection, parking_id: i32, user_id: i32) -> QueryResult<usize> {
+    insert_into(parkings_consumers::dsl::parkings_consumers)
+        .values((
+            parkings_consumers::dsl::parking_id.eq(parking_id),
+            parkings_consumers::dsl::consumer_id.eq(user_id),
+        ))
+        .on_conflict((parkings_consumers::dsl::parking_id, parkings_consumers::dsl::consumer_id))
+        .do_update()
+        .set(parkings_consumers::dsl::expires_at.eq::<Option<NaiveDateTime>>(None))
+        .execute(db_conn)
+}
+
+pub async fn list_members(
+    parking_id: i32,
+    db: Db,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    administered_parking(db_conn, parking_id, user_id)?;
+    let members = active_members(db_conn, parking_id).map_err(|_| reject::reject())?;
+    Ok(reply::json(&members))
+}
+
+pub async fn set_membership_expiry(
+    parking_id: i32,
+    consumer_id: i32,
+    body: MembershipExpiryRequest,
+    db: Db,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let parking = administered_parking(db_conn, parking_id, user_id)?;
+    if !is_member(db_conn, parking_id, consumer_id) {
+        return Err(reject::not_found());
+    }
+    let membership = diesel::update(parkings_consumers::dsl::parkings_consumers.find((parking_id, consumer_id)))
+        .set(parkings_consumers::dsl::expires_at.eq(body.expires_at))
+        .get_result::<ParkingConsumer>(db_conn)
+        .map_err(|_| reject::reject())?;
+    let until = body
+        .expires_at
+        .map(|at| at.to_string())
+        .unwrap_or_else(|| "never".to_string());
+    record_event(
+        db_conn,
+        Some(parking.admin_id),
+        Some(consumer_id),
+        MEMBERSHIP_EXPIRY_SET,
+        Some(format!("parking {} until {}", parking_id, until)),
+    );
+    Ok(reply::json(&membership))
+}
+
+/// Deletes expired memberships and returns them.
+pub fn remove_expired_memberships(db_conn: &PgConnection) -> QueryResult<Vec<ParkingConsumer>> {
+    let removed = diesel::delete(
+        parkings_consumers::dsl::parkings_consumers
+            .filter(parkings_consumers::dsl::expires_at.le(Utc::now().naive_utc())),
+    )
+    .get_results::<ParkingConsumer>(db_conn)?;
+    for membership in &removed {
+        record_event(
+            db_conn,
+            None,
+            Some(membership.consumer_id),
+            MEMBERSHIP_EXPIRED,
+            Some(format!("parking {}", membership.parking_id)),
+        );
+    }
+    Ok(removed)
+}
+
+/// Removes expired memberships every `MEMBERSHIP_CLEANUP_INTERVAL_SECS` (3600 by default).
+pub async fn clean_up_memberships(db: Db) {
+    let interval = env::var("MEMBERSHIP_CLEANUP_INTERVAL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(3600);
+    loop {
+        {
+            let db_conn_mutex = db.lock().unwrap();
+            if let Err(e) = remove_expired_memberships(db_conn_mutex.deref()) {
+                eprintln!("expired memberships not removed: {:?}", e);
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+    }
+}
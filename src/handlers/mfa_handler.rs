@@ -0,0 +1,271 @@
+use chrono::{Duration, Utc};
+use std::env;
+use std::ops::Deref;
+use warp::{http::StatusCode, reject, reply, Rejection, Reply};
+
+use crate::db::db_schema::{recovery_codes, totp_credentials, users};
+use crate::handlers::audit_handler::{
+    record_event, MFA_DISABLED, MFA_ENABLED, RECOVERY_CODES_REGENERATED,
+};
+use crate::handlers::error_handler;
+use crate::handlers::one_time_token_handler::{consume_token, peek_token, MFA_CHALLENGE};
+use crate::handlers::session_handler::open_session;
+use crate::handlers::user_handler::login_reply;
+use crate::models::session::ClientInfo;
+use crate::models::totp_credential::TotpCredential;
+use crate::models::user::User;
+use crate::routes::Db;
+use crate::security::throttle::{AttemptKey, SharedThrottle};
+use crate::security::{hash_token, totp};
+use diesel::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MfaCodeRequest {
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MfaLoginRequest {
+    pub mfa_token: String,
+    pub code: String,
+    /// Repeats the cookie preference of the login that issued the challenge.
+    #[serde(default)]
+    pub cookie: bool,
+}
+
+pub fn mfa_challenge_ttl() -> Duration {
+    Duration::minutes(5)
+}
+
+fn find_credential(db_conn: &PgConnection, user_id: i32) -> QueryResult<Option<TotpCredential>> {
+    totp_credentials::dsl::totp_credentials
+        .find(user_id)
+        .first::<TotpCredential>(db_conn)
+        .optional()
+}
+
+pub fn mfa_enabled(db_conn: &PgConnection, user_id: i32) -> QueryResult<bool> {
+    Ok(find_credential(db_conn, user_id)?.is_some_and(|c| c.is_confirmed()))
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+/// Replaces any previous recovery codes; only their hashes are stored.
+fn generate_recovery_codes(db_conn: &PgConnection, user_id: i32) -> QueryResult<Vec<String>> {
+    diesel::delete(recovery_codes::dsl::recovery_codes.filter(recovery_codes::dsl::user_id.eq(user_id)))
+        .execute(db_conn)?;
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = hex::encode(rand::thread_rng().gen::<[u8; 5]>());
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect();
+    let rows: Vec<_> = codes
+        .iter()
+        .map(|code| {
+            (
+                recovery_codes::dsl::user_id.eq(user_id),
+                recovery_codes::dsl::code_hash.eq(hash_token(&normalize_recovery_code(code))),
+            )
+        })
+        .collect();
+    insert_into(recovery_codes::dsl::recovery_codes)
+        .values(&rows)
+        .execute(db_conn)?;
+    Ok(codes)
+}
+
+/// Accepts a current TOTP code that was not used before, or an unused recovery code,
+/// which is spent by this call.
+pub fn verify_second_factor(db_conn: &PgConnection, user_id: i32, code: &str) -> QueryResult<bool> {
+    let credential = match find_credential(db_conn, user_id)? {
+        Some(credential) if credential.is_confirmed() => credential,
+        _ => return Ok(false),
+    };
+    if let Some(step) = totp::verify(&credential.secret, code) {
+        let updated = diesel::update(
+            totp_credentials::dsl::totp_credentials
+                .find(user_id)
+                .filter(totp_credentials::dsl::last_used_step.lt(step)),
+        )
+        .set(totp_credentials::dsl::last_used_step.eq(step))
+        .execute(db_conn)?;
+        return Ok(updated == 1);
+    }
+    let updated = diesel::update(
+        recovery_codes::dsl::recovery_codes
+            .filter(recovery_codes::dsl::user_id.eq(user_id))
+            .filter(recovery_codes::dsl::code_hash.eq(hash_token(&normalize_recovery_code(code))))
+            .filter(recovery_codes::dsl::used_at.is_null()),
+    )
+    .set(recovery_codes::dsl::used_at.eq(Some(Utc::now().naive_utc())))
+    .execute(db_conn)?;
+    Ok(updated == 1)
+}
+
+/// Starts (or restarts) enrollment; the secret is inactive until confirmed with a code.
+pub async fn enroll_totp(db: Db, user_id: Option<i32>) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    let user = users::dsl::users
+        .find(user_id)
+        .first::<User>(db_conn)
+        .map_err(|_| reject::custom(error_handler::Error::NoPermissionError))?;
+    if mfa_enabled(db_conn, user_id).map_err(|_| reject::reject())? {
+        return Err(reject::custom(error_handler::Error::MfaAlreadyEnabledError));
+    }
+
+    let secret = totp::generate_secret();
+    diesel::delete(totp_credentials::dsl::totp_credentials.find(user_id))
+        .execute(db_conn)
+        .map_err(|_| reject::reject())?;
+    insert_into(totp_credentials::dsl::totp_credentials)
+        .values((
+            totp_credentials::dsl::user_id.eq(user_id),
+            totp_credentials::dsl::secret.eq(&secret),
+        ))
+        .execute(db_conn)
+        .map_err(|_| reject::reject())?;
+
+    let issuer = env::var("TOTP_ISSUER").unwrap_or_else(|_| "Parkings".to_string());
+    let account = user
+        .login
+        .or(user.email)
+        .unwrap_or_else(|| format!("user {}", user_id));
+    Ok(reply::json(&TotpEnrollment {
+        otpauth_uri: totp::otpauth_uri(&issuer, &account, &secret),
+        secret,
+    }))
+}
+
+pub async fn confirm_totp(
+    body: MfaCodeRequest,
+    db: Db,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    let credential = match find_credential(db_conn, user_id).map_err(|_| reject::reject())? {
+        Some(credential) if credential.is_confirmed() => {
+            return Err(reject::custom(error_handler::Error::MfaAlreadyEnabledError))
+        }
+        Some(credential) => credential,
+        None => return Err(reject::custom(error_handler::Error::InvalidMfaCodeError)),
+    };
+    let step = totp::verify(&credential.secret, &body.code)
+        .ok_or_else(|| reject::custom(error_handler::Error::InvalidMfaCodeError))?;
+
+    let recovery_codes = db_conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            diesel::update(totp_credentials::dsl::totp_credentials.find(user_id))
+                .set((
+                    totp_credentials::dsl::confirmed_at.eq(Some(Utc::now().naive_utc())),
+                    totp_credentials::dsl::last_used_step.eq(step),
+                ))
+                .execute(db_conn)?;
+            generate_recovery_codes(db_conn, user_id)
+        })
+        .map_err(|_| reject::reject())?;
+    record_event(db_conn, Some(user_id), Some(user_id), MFA_ENABLED, None);
+    Ok(reply::json(&RecoveryCodesResponse { recovery_codes }))
+}
+
+pub async fn disable_totp(
+    body: MfaCodeRequest,
+    db: Db,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    if !verify_second_factor(db_conn, user_id, &body.code).map_err(|_| reject::reject())? {
+        return Err(reject::custom(error_handler::Error::InvalidMfaCodeError));
+    }
+    db_conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            diesel::delete(totp_credentials::dsl::totp_credentials.find(user_id)).execute(db_conn)?;
+            diesel::delete(
+                recovery_codes::dsl::recovery_codes.filter(recovery_codes::dsl::user_id.eq(user_id)),
+            )
+            .execute(db_conn)
+        })
+        .map_err(|_| reject::reject())?;
+    record_event(db_conn, Some(user_id), Some(user_id), MFA_DISABLED, None);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn regenerate_recovery_codes(
+    body: MfaCodeRequest,
+    db: Db,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    if !verify_second_factor(db_conn, user_id, &body.code).map_err(|_| reject::reject())? {
+        return Err(reject::custom(error_handler::Error::InvalidMfaCodeError));
+    }
+    let recovery_codes = generate_recovery_codes(db_conn, user_id).map_err(|_| reject::reject())?;
+    record_event(db_conn, Some(user_id), Some(user_id), RECOVERY_CODES_REGENERATED, None);
+    Ok(reply::json(&RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Second login step: trades the challenge token from `log_in` and a code for a session token.
+/// The challenge survives wrong codes so the user can retry until it expires or throttling kicks in.
+pub async fn complete_login(
+    body: MfaLoginRequest,
+    db: Db,
+    throttle: SharedThrottle,
+    client: ClientInfo,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let user_id = peek_token(db_conn, MFA_CHALLENGE, &body.mfa_token)
+        .ok_or_else(|| reject::custom(error_handler::Error::InvalidTokenError))?;
+    let account_key = AttemptKey::SecondFactor(user_id);
+    let attempt_keys = account_key.clone().with_ip(client.ip);
+    throttle
+        .check(&attempt_keys)
+        .map_err(|retry_after| reject::custom(error_handler::Error::TooManyAttemptsError(retry_after)))?;
+
+    if !verify_second_factor(db_conn, user_id, &body.code).map_err(|_| reject::reject())? {
+        throttle.record_failure(&attempt_keys);
+        return Err(reject::custom(error_handler::Error::InvalidMfaCodeError));
+    }
+    consume_token(db_conn, MFA_CHALLENGE, &body.mfa_token)
+        .ok_or_else(|| reject::custom(error_handler::Error::InvalidTokenError))?;
+    throttle.record_success(&[account_key]);
+    let token = open_session(db_conn, user_id, &client).map_err(reject::custom)?;
+    Ok(login_reply(token, body.cookie))
+}
@@ -1,4 +1,22 @@
+pub mod api_key_handler;
+pub mod audit_handler;
+pub mod email_verification_handler;
 pub mod error_handler;
+pub mod guest_handler;
+pub mod introspection_handler;
+pub mod join_request_handler;
+pub mod magic_link_handler;
+pub mod membership_handler;
+pub mod mfa_handler;
+pub mod oidc_handler;
+pub mod one_time_token_handler;
 pub mod parking_handler;
+pub mod passkey_handler;
 pub mod parking_password_handler;
+pub mod parking_settings_handler;
+pub mod password_reset_handler;
+pub mod profile_handler;
+pub mod session_handler;
+pub mod system_admin_handler;
 pub mod user_handler;
+pub mod user_import_handler;
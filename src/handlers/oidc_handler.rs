@@ -0,0 +1,137 @@
+use std::ops::Deref;
+use warp::{reject, reply, Rejection, Reply};
+
+use crate::db::db_schema::{oidc_identities, users};
+use crate::handlers::audit_handler::{record_event, OIDC_LINKED, OIDC_LOGIN};
+use crate::handlers::error_handler;
+use crate::handlers::session_handler::open_session;
+use crate::handlers::user_handler::login_reply;
+use crate::models::oidc_identity::OidcIdentity;
+use crate::models::session::ClientInfo;
+use crate::models::user::{normalize_email, User};
+use crate::routes::Db;
+use crate::security::cookie::{add_expired_oidc_state_cookie, add_oidc_state_cookie};
+use crate::security::oidc::{OidcError, SharedOidc, VerifiedIdentity, PENDING_LOGIN_TTL};
+use diesel::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcCallbackRequest {
+    pub code: String,
+    pub state: String,
+    /// Browser clients ask for the session in a cookie instead of the response body.
+    #[serde(default)]
+    pub cookie: bool,
+}
+
+fn oidc_rejection(e: OidcError) -> Rejection {
+    match e {
+        OidcError::NotConfigured => reject::custom(error_handler::Error::OidcNotConfiguredError),
+        OidcError::TooManyPendingLogins => reject::custom(error_handler::Error::TooManyAttemptsError(60)),
+        e => {
+            eprintln!("oidc login failed: {}", e);
+            reject::custom(error_handler::Error::OidcLoginError)
+        }
+    }
+}
+
+/// Starts the authorization code flow. A signed-in caller links the identity to their account instead.
+/// The state also goes into a cookie, so only this browser can complete the flow.
+pub async fn start_login(oidc: SharedOidc, user_id: Option<i32>) -> Result<impl Reply, Rejection> {
+    let request = oidc.start(user_id).await.map_err(oidc_rejection)?;
+    let mut response = reply::json(&request).into_response();
+    add_oidc_state_cookie(&mut response, &request.state, PENDING_LOGIN_TTL.as_secs() as i64);
+    Ok(response)
+}
+
+pub async fn finish_login(
+    body: OidcCallbackRequest,
+    state_cookie: Option<String>,
+    db: Db,
+    oidc: SharedOidc,
+    client: ClientInfo,
+) -> Result<impl Reply, Rejection> {
+    // Talk to the provider before taking the connection lock.
+    let identity = oidc
+        .finish(&body.code, &body.state, state_cookie.as_deref())
+        .await
+        .map_err(oidc_rejection)?;
+
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let linked = oidc_identities::dsl::oidc_identities
+        .find((&identity.issuer, &identity.subject))
+        .first::<OidcIdentity>(db_conn)
+        .optional()
+        .map_err(|_| reject::reject())?;
+
+    let user_id = match (linked, identity.link_user_id) {
+        (Some(linked), None) => linked.user_id,
+        (Some(linked), Some(link_user_id)) if linked.user_id == link_user_id => link_user_id,
+        (Some(_), Some(_)) => return Err(reject::custom(error_handler::Error::OidcLoginError)),
+        (None, Some(link_user_id)) => {
+            link_identity(db_conn, &identity, link_user_id).map_err(|_| reject::reject())?;
+            record_event(db_conn, Some(link_user_id), Some(link_user_id), OIDC_LINKED, Some(identity.issuer.clone()));
+            link_user_id
+        }
+        (None, None) => db_conn
+            .transaction::<_, diesel::result::Error, _>(|| {
+                let user_id = create_oidc_user(db_conn, &identity)?;
+                link_identity(db_conn, &identity, user_id)?;
+                Ok(user_id)
+            })
+            .map_err(|_| reject::reject())?,
+    };
+
+    let token = open_session(db_conn, user_id, &client).map_err(reject::custom)?;
+    record_event(db_conn, Some(user_id), Some(user_id), OIDC_LOGIN, Some(identity.issuer));
+    let mut response = login_reply(token, body.cookie);
+    add_expired_oidc_state_cookie(&mut response);
+    Ok(response)
+}
+
+pub fn identities_for_user(db_conn: &PgConnection, user_id: i32) -> QueryResult<Vec<OidcIdentity>> {
+    oidc_identities::dsl::oidc_identities
+        .filter(oidc_identities::dsl::user_id.eq(user_id))
+        .order(oidc_identities::dsl::created_at.asc())
+        .load::<OidcIdentity>(db_conn)
+}
+
+fn link_identity(
+    db_conn: &PgConnection,
+    identity: &VerifiedIdentity,
+    user_id: i32,
+) -> QueryResult<usize> {
+    insert_into(oidc_identities::dsl::oidc_identities)
+        .values((
+            oidc_identities::dsl::issuer.eq(&identity.issuer),
+            oidc_identities::dsl::subject.eq(&identity.subject),
+            oidc_identities::dsl::user_id.eq(user_id),
+        ))
+        .execute(db_conn)
+}
+
+/// Accounts created through single sign-on have no local login or password but are not guests.
+/// The provider-verified email is kept, and counts as verified, only when no other account already uses it.
+fn create_oidc_user(db_conn: &PgConnection, identity: &VerifiedIdentity) -> QueryResult<i32> {
+    let email = identity.email.as_deref().and_then(normalize_email);
+    let email_taken = match &email {
+        Some(email) => users::dsl::users
+            .filter(users::dsl::email.eq(email))
+            .first::<User>(db_conn)
+            .optional()?
+            .is_some(),
+        None => false,
+    };
+    insert_into(users::dsl::users)
+        .values((
+            users::dsl::email.eq(email.clone().filter(|_| !email_taken)),
+            users::dsl::email_verified.eq(email.is_some() && !email_taken),
+            users::dsl::display_name.eq(identity.name.clone()),
+            users::dsl::guest.eq(false),
+        ))
+        .returning(users::dsl::user_id)
+        .get_result(db_conn)
+}
@@ -0,0 +1,101 @@
+use chrono::{Duration, Utc};
+use diesel::*;
+
+use crate::db::db_schema::one_time_tokens;
+use crate::security::{generate_token, hash_token};
+
+pub const PASSWORD_RESET: &str = "password_reset";
+pub const MFA_CHALLENGE: &str = "mfa_challenge";
+pub const EMAIL_VERIFICATION: &str = "email_verification";
+pub const MAGIC_LINK: &str = "magic_link";
+pub const DEVICE_PAIRING: &str = "device_pairing";
+
+/// Stores a hash of a fresh random token and returns the plain value, which is never persisted.
+pub fn issue_token(
+    db_conn: &PgConnection,
+    user_id: i32,
+    purpose: &str,
+    ttl: Duration,
+) -> QueryResult<String> {
+    let token = generate_token();
+    store_token(db_conn, user_id, purpose, &token, ttl)?;
+    Ok(token)
+}
+
+/// Like `issue_token` for codes generated by the caller, e.g. short ones meant to be typed in.
+/// Returns the id of the stored token.
+pub fn store_token(
+    db_conn: &PgConnection,
+    user_id: i32,
+    purpose: &str,
+    token: &str,
+    ttl: Duration,
+) -> QueryResult<i32> {
+    let now = Utc::now().naive_utc();
+    insert_into(one_time_tokens::dsl::one_time_tokens)
+        .values((
+            one_time_tokens::dsl::user_id.eq(user_id),
+            one_time_tokens::dsl::purpose.eq(purpose),
+            one_time_tokens::dsl::token_hash.eq(hash_token(token)),
+            one_time_tokens::dsl::created_at.eq(now),
+            one_time_tokens::dsl::expires_at.eq(now + ttl),
+        ))
+        .returning(one_time_tokens::dsl::token_id)
+        .get_result(db_conn)
+}
+
+/// Marks the token as used and returns its owner; expired, reused and unknown tokens yield `None`.
+pub fn consume_token(db_conn: &PgConnection, purpose: &str, token: &str) -> Option<i32> {
+    let now = Utc::now().naive_utc();
+    diesel::update(
+        one_time_tokens::dsl::one_time_tokens
+            .filter(one_time_tokens::dsl::token_hash.eq(hash_token(token)))
+            .filter(one_time_tokens::dsl::purpose.eq(purpose))
+            .filter(one_time_tokens::dsl::used_at.is_null())
+            .filter(one_time_tokens::dsl::expires_at.gt(now)),
+    )
+    .set(one_time_tokens::dsl::used_at.eq(Some(now)))
+    .returning(one_time_tokens::dsl::user_id)
+    .get_result::<i32>(db_conn)
+    .ok()
+}
+
+/// Like `consume_token` for a token the caller names by its id as well.
+pub fn consume_token_with_id(db_conn: &PgConnection, purpose: &str, token_id: i32, token: &str) -> Option<i32> {
+    let now = Utc::now().naive_utc();
+    diesel::update(
+        one_time_tokens::dsl::one_time_tokens
+            .find(token_id)
+            .filter(one_time_tokens::dsl::token_hash.eq(hash_token(token)))
+            .filter(one_time_tokens::dsl::purpose.eq(purpose))
+            .filter(one_time_tokens::dsl::used_at.is_null())
+            .filter(one_time_tokens::dsl::expires_at.gt(now)),
+    )
+    .set(one_time_tokens::dsl::used_at.eq(Some(now)))
+    .returning(one_time_tokens::dsl::user_id)
+    .get_result::<i32>(db_conn)
+    .ok()
+}
+
+/// Resolves the owner of a still usable token without marking it as used.
+pub fn peek_token(db_conn: &PgConnection, purpose: &str, token: &str) -> Option<i32> {
+    one_time_tokens::dsl::one_time_tokens
+        .filter(one_time_tokens::dsl::token_hash.eq(hash_token(token)))
+        .filter(one_time_tokens::dsl::purpose.eq(purpose))
+        .filter(one_time_tokens::dsl::used_at.is_null())
+        .filter(one_time_tokens::dsl::expires_at.gt(Utc::now().naive_utc()))
+        .select(one_time_tokens::dsl::user_id)
+        .first::<i32>(db_conn)
+        .ok()
+}
+
+pub fn invalidate_tokens(db_conn: &PgConnection, user_id: i32, purpose: &str) -> QueryResult<usize> {
+    diesel::update(
+        one_time_tokens::dsl::one_time_tokens
+            .filter(one_time_tokens::dsl::user_id.eq(user_id))
+            .filter(one_time_tokens::dsl::purpose.eq(purpose))
+            .filter(one_time_tokens::dsl::used_at.is_null()),
+    )
+    .set(one_time_tokens::dsl::used_at.eq(Some(Utc::now().naive_utc())))
+    .execute(db_conn)
+}
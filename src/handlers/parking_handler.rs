@@ -1,25 +1,42 @@
+use chrono::Utc;
 use std::convert::Infallible;
 use warp::{http::StatusCode, reject, reply, Rejection, Reply};
 
 
 use crate::routes::Db;
 
+use crate::db::contains_pattern;
 use crate::db::db_schema::users;
 use crate::db::db_schema::{parkings, parkings_consumers};
+use crate::handlers::audit_handler::{record_event, PARKING_TRANSFERRED};
 use crate::handlers::error_handler;
-use crate::models::parking::{Parking, ParkingWithoutPassword};
+use crate::handlers::guest_handler::issue_recovery_code;
+use crate::handlers::join_request_handler::request_to_join;
+use crate::handlers::membership_handler::{add_member, ensure_capacity, is_member};
+use crate::mail::SharedMailer;
+use crate::models::parking::{Membership, Parking, ParkingWithoutPassword, PublicParking};
 use crate::models::parking_consumer::ParkingConsumer;
+use crate::models::session::ClientInfo;
 use crate::models::user::User;
 use diesel::result::Error;
+use diesel::sql_types::Text;
 use diesel::*;
 use serde::{Deserialize, Serialize};
 use std::ops::Deref;
-use crate::security::create_jwt;
+use crate::handlers::session_handler::open_session;
+use crate::security::cookie::add_session_cookies;
+use crate::security::generate_token;
+use crate::security::throttle::{AttemptKey, SharedThrottle};
 
 
-fn get_consumed_parkings(db_conn: &PgConnection, user_id: i32) -> Vec<Parking> {
+pub fn get_consumed_parkings(db_conn: &PgConnection, user_id: i32) -> Vec<Parking> {
     let consumers: Vec<ParkingConsumer> = match parkings_consumers::dsl::parkings_consumers
         .filter(parkings_consumers::dsl::consumer_id.eq(user_id))
+        .filter(
+            parkings_consumers::dsl::expires_at
+                .is_null()
+                .or(parkings_consumers::dsl::expires_at.gt(Utc::now().naive_utc())),
+        )
         .load::<ParkingConsumer>(db_conn)
     {
         Ok(p) => p,
@@ -40,6 +57,48 @@ fn get_consumed_parkings(db_conn: &PgConnection, user_id: i32) -> Vec<Parking> {
         .collect()
 }
 
+pub fn get_administered_parkings(db_conn: &PgConnection, user_id: i32) -> QueryResult<Vec<Parking>> {
+    parkings::dsl::parkings
+        .filter(parkings::dsl::admin_id.eq(user_id))
+        .load::<Parking>(db_conn)
+}
+
+pub fn get_memberships(db_conn: &PgConnection, user_id: i32) -> QueryResult<Vec<Membership>> {
+    let membership = |parking: Parking, role: &str| Membership {
+        parking_id: parking.parking_id,
+        name: parking.name,
+        role: role.to_string(),
+    };
+    let mut memberships: Vec<Membership> = get_administered_parkings(db_conn, user_id)?
+        .into_iter()
+        .map(|parking| membership(parking, "admin"))
+        .collect();
+    memberships.extend(
+        get_consumed_parkings(db_conn, user_id)
+            .into_iter()
+            .map(|parking| membership(parking, "consumer")),
+    );
+    Ok(memberships)
+}
+
+pub async fn list_user_memberships(
+    user_id: i32,
+    db: Db,
+    _client: String,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    if users::dsl::users.find(user_id).first::<User>(db_conn).is_err() {
+        return Err(reject::not_found());
+    }
+    match get_memberships(db_conn, user_id) {
+        Ok(memberships) => Ok(reply::json(&memberships)),
+        Err(_) => Err(reject()),
+    }
+}
+
+/// Only memberships are listed; requests waiting for approval live in `join_requests`.
 pub async fn list_parkings(db: Db, user_id: Option<i32>) -> Result<impl Reply, Rejection> {
     let db_conn_mutex = db.lock().unwrap();
     let db_conn = db_conn_mutex.deref();
@@ -50,27 +109,63 @@ pub async fn list_parkings(db: Db, user_id: Option<i32>) -> Result<impl Reply, R
             .map(|parking| parking.to_parking_without_password())
             .collect();
 
-    let mut parkings: Vec<ParkingWithoutPassword> = match parkings::dsl::parkings
-        .filter(parkings::dsl::admin_id.eq(user_id.unwrap()))
-        .load::<Parking>(db_conn)
-    {
-        Ok(result) => result
-            .iter()
-            .map(|parking| parking.to_parking_without_password())
-            .collect::<Vec<ParkingWithoutPassword>>(),
-        Err(_) => return Err(reject()),
-    };
+    let mut parkings: Vec<ParkingWithoutPassword> =
+        match get_administered_parkings(db_conn, user_id.unwrap()) {
+            Ok(result) => result
+                .iter()
+                .map(|parking| parking.to_parking_without_password())
+                .collect::<Vec<ParkingWithoutPassword>>(),
+            Err(_) => return Err(reject()),
+        };
     consumed_parkings.append(&mut parkings);
     Ok(reply::json::<Vec<ParkingWithoutPassword>>(
         &consumed_parkings,
     ))
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchParkingsQuery {
+    #[serde(default)]
+    pub q: String,
+    pub limit: Option<i64>,
+}
+
+sql_function!(fn similarity(x: Text, y: Text) -> Float4);
+
+/// Public parkings whose name contains the query, ignoring case, or is similar to it by
+/// trigram similarity, best matches first. Private parkings never show up.
+pub async fn search_parkings(query: SearchParkingsQuery, db: Db) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let term = query.q.trim();
+    if term.is_empty() {
+        return Ok(reply::json(&Vec::<PublicParking>::new()));
+    }
+    let found = parkings::dsl::parkings
+        .filter(parkings::dsl::public.eq(true))
+        .filter(
+            parkings::dsl::name
+                .ilike(contains_pattern(term))
+                .or(similarity(parkings::dsl::name, term).gt(0.3)),
+        )
+        .order(similarity(parkings::dsl::name, term).desc())
+        .then_order_by(parkings::dsl::name)
+        .limit(query.limit.unwrap_or(20).clamp(1, 100))
+        .load::<Parking>(db_conn)
+        .map_err(|_| reject::reject())?;
+    let results: Vec<PublicParking> = found.iter().map(|parking| parking.to_public_parking()).collect();
+    Ok(reply::json(&results))
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateParkingRequest {
     pub name: String,
     pub password: String,
+    #[serde(default)]
+    pub public: bool,
 }
 
 pub async fn create_parking(
@@ -92,6 +187,7 @@ pub async fn create_parking(
                         parkings::dsl::admin_id.eq(user_id.unwrap()),
                         parkings::dsl::name.eq(parking.name.clone()),
                         parkings::dsl::password.eq(parking.password),
+                        parkings::dsl::public.eq(parking.public),
                     ))
                     .returning(parkings::dsl::parking_id)
                     .get_result::<i32>(db_conn);
@@ -110,62 +206,209 @@ pub async fn create_parking(
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct JoinParkingResponse {
+    /// Left out when the session was put in a cookie.
     pub token: Option<String>,
-    pub parking: Parking,
+    /// Set instead of `token` for cookie sessions, see `CookieLoginResponse`.
+    pub csrf_token: Option<String>,
+    /// The parking requires approval and the membership waits for its admin.
+    pub pending: bool,
+    /// Handed out with a new guest account; it signs the guest back in after a reinstall.
+    pub recovery_code: Option<String>,
+    pub parking: ParkingWithoutPassword,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct JoinParkingRequest {
+    /// Identifies the parking together with `password`.
+    #[serde(default)]
     pub name: String,
-    pub password: String,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// A public parking from search, joined without the password through a join request
+    /// the admin approves. Join requests need a signed-in caller.
+    #[serde(default)]
+    pub parking_id: Option<i32>,
+    /// Browser clients joining as a guest ask for the session in a cookie.
+    #[serde(default)]
+    pub cookie: bool,
 }
 
 pub async fn join_parking(
     body: JoinParkingRequest,
     db: Db,
     user_id: Option<i32>,
-    jwt_secret: String,
+    throttle: SharedThrottle,
+    mailer: SharedMailer,
+    client: ClientInfo,
 ) -> Result<impl Reply, Rejection> {
+    let account_key = AttemptKey::Parking(body.name.clone());
+    let attempt_keys = match &body.password {
+        Some(_) => account_key.clone().with_ip(client.ip),
+        // every password-less join mails the admin, so it is throttled by address and
+        // refused when the address is unknown
+        None => vec![AttemptKey::Ip(
+            client.ip.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?,
+        )],
+    };
+    throttle
+        .check(&attempt_keys)
+        .map_err(|retry_after| reject::custom(error_handler::Error::TooManyAttemptsError(retry_after)))?;
+
     let db_conn_mutex = db.lock().unwrap();
     let db_conn = db_conn_mutex.deref();
-    let (user_id, token) = match user_id {
+
+    let parking: Result<Parking, Error> = match (&body.password, body.parking_id) {
+        (Some(parking_password), _) => parkings::dsl::parkings
+            .filter(
+                parkings::dsl::name
+                    .eq(&body.name)
+                    .and(parkings::dsl::password.eq(parking_password)),
+            )
+            .first::<Parking>(db_conn),
+        (None, Some(parking_id)) => parkings::dsl::parkings
+            .find(parking_id)
+            .filter(parkings::dsl::public.eq(true))
+            .first::<Parking>(db_conn),
+        (None, None) => Err(Error::NotFound),
+    };
+    let valid_parking = match parking {
+        Ok(valid_parking) => valid_parking,
+        Err(_) => {
+            throttle.record_failure(&attempt_keys);
+            return Err(reject::custom(error_handler::Error::WrongParkingError));
+        }
+    };
+    // only a correct password may lift the lockout of the parking
+    if body.password.is_some() {
+        throttle.record_success(&[account_key]);
+    } else {
+        throttle.record_failure(&attempt_keys);
+    }
+    let require_approval = valid_parking.require_approval || body.password.is_none();
+    // a join request mails the admin, so it is not filed for a guest created just for it
+    if require_approval && user_id.is_none() {
+        return Err(reject::custom(error_handler::Error::SignInRequiredError));
+    }
+
+    let user = user_id.and_then(|id| users::dsl::users.find(id).first::<User>(db_conn).ok());
+    if !valid_parking.allowed_email_domains.is_empty() {
+        // guests would be created without an address, so they have to register first
+        match &user {
+            Some(user) if !user.is_guest() => {
+                if !user.has_verified_email() {
+                    return Err(reject::custom(error_handler::Error::EmailVerificationRequiredError));
+                }
+                if !user.email.as_deref().is_some_and(|email| valid_parking.allows_email(email)) {
+                    return Err(reject::custom(error_handler::Error::EmailDomainNotAllowedError));
+                }
+            }
+            _ => return Err(reject::custom(error_handler::Error::RegistrationRequiredError)),
+        }
+    }
+    if valid_parking.require_verified_email && !user.as_ref().is_some_and(|user| user.has_verified_email()) {
+        return Err(reject::custom(error_handler::Error::EmailVerificationRequiredError));
+    }
+
+    let already_member = user_id.is_some_and(|id| is_member(db_conn, valid_parking.parking_id, id));
+    // approval mode checks the capacity when the request is approved
+    if !already_member && !require_approval {
+        ensure_capacity(db_conn, &valid_parking)?;
+    }
+
+    let (user_id, token, recovery_code) = match user_id {
         None => {
             let user = insert_into(users::dsl::users)
                 .values((
                     users::dsl::login.eq::<Option<String>>(Option::None),
                     users::dsl::password.eq::<Option<String>>(Option::None),
+                    users::dsl::guest.eq(true),
                 ))
                 .returning(users::dsl::users::all_columns())
                 .get_results::<User>(db_conn);
             let id = user.unwrap().first().unwrap().id;
-            let token = create_jwt(&id, jwt_secret.as_bytes()).unwrap();
-            (id, Some(token))
+            let token = open_session(db_conn, id, &client).unwrap();
+            let recovery_code = issue_recovery_code(db_conn, id).map_err(|_| reject::reject())?;
+            (id, Some(token), Some(recovery_code))
         }
-        Some(id) => (id, None),
+        Some(id) => (id, None, None),
     };
 
-    let parking: Result<Parking, Error> = parkings::dsl::parkings
-        .filter(
-            parkings::dsl::name
-                .eq(body.name)
-                .and(parkings::dsl::password.eq(body.password)),
-        )
-        .first::<Parking>(db_conn);
+    let pending = require_approval && !already_member;
+    if pending {
+        request_to_join(db_conn, &mailer, &valid_parking, user_id).map_err(|_| reject::reject())?;
+    } else if !already_member {
+        add_member(db_conn, valid_parking.parking_id, user_id).map_err(|_| reject::reject())?;
+    }
+    let csrf_token = token.as_ref().filter(|_| body.cookie).map(|_| generate_token());
+    let mut response = reply::json::<JoinParkingResponse>(&JoinParkingResponse {
+        token: token.clone().filter(|_| csrf_token.is_none()),
+        csrf_token: csrf_token.clone(),
+        pending,
+        recovery_code,
+        parking: valid_parking.to_parking_without_password(),
+    })
+    .into_response();
+    if let (Some(token), Some(csrf_token)) = (&token, &csrf_token) {
+        add_session_cookies(&mut response, token, csrf_token);
+    }
+    if pending {
+        *response.status_mut() = StatusCode::ACCEPTED;
+    }
+    Ok(response)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferParkingRequest {
+    pub admin_id: i32,
+}
+
+/// Hands a parking over to one of its consumers; the previous admin stays on as a consumer.
+pub async fn transfer_parking(
+    parking_id: i32,
+    body: TransferParkingRequest,
+    db: Db,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let owner_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    let parking = parkings::dsl::parkings
+        .find(parking_id)
+        .first::<Parking>(db_conn)
+        .map_err(|_| reject::custom(error_handler::Error::WrongParkingError))?;
+    if parking.admin_id != owner_id {
+        return Err(reject::custom(error_handler::Error::NoPermissionError));
+    }
+    if !is_member(db_conn, parking_id, body.admin_id) {
+        return Err(reject::custom(error_handler::Error::WrongParkingError));
+    }
 
-    match parking {
-        Ok(valid_parking) => {
+    db_conn
+        .transaction::<_, Error, _>(|| {
+            diesel::delete(parkings_consumers::dsl::parkings_consumers.find((parking_id, body.admin_id)))
+                .execute(db_conn)?;
             insert_into(parkings_consumers::dsl::parkings_consumers)
                 .values((
-                    parkings_consumers::dsl::parking_id.eq(valid_parking.parking_id),
-                    parkings_consumers::dsl::consumer_id.eq(user_id),
+                    parkings_consumers::dsl::parking_id.eq(parking_id),
+                    parkings_consumers::dsl::consumer_id.eq(owner_id),
                 ))
-                .execute(db_conn);
-            Ok(reply::json::<JoinParkingResponse>(&JoinParkingResponse {
-                token,
-                parking: valid_parking,
-            }))
-        }
-        Err(_) => Err(reject::custom(error_handler::Error::WrongParkingError)),
-    }
+                .on_conflict_do_nothing()
+                .execute(db_conn)?;
+            diesel::update(parkings::dsl::parkings.find(parking_id))
+                .set(parkings::dsl::admin_id.eq(body.admin_id))
+                .execute(db_conn)?;
+            Ok(())
+        })
+        .map_err(|_| reject::reject())?;
+    record_event(
+        db_conn,
+        Some(owner_id),
+        Some(body.admin_id),
+        PARKING_TRANSFERRED,
+        Some(format!("parking {}", parking_id)),
+    );
+    Ok(StatusCode::OK)
 }
@@ -0,0 +1,57 @@
+use std::ops::Deref;
+use warp::{reject, reply, Rejection, Reply};
+
+use crate::db::db_schema::parkings;
+use crate::handlers::error_handler;
+use crate::models::parking::{Parking, UpdateParkingSettingsRequest};
+use crate::routes::Db;
+use diesel::*;
+
+/// Loads the parking when `user_id` administers it.
+pub fn administered_parking(
+    db_conn: &PgConnection,
+    parking_id: i32,
+    user_id: Option<i32>,
+) -> Result<Parking, Rejection> {
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+    let parking = parkings::dsl::parkings
+        .find(parking_id)
+        .first::<Parking>(db_conn)
+        .map_err(|_| reject::custom(error_handler::Error::WrongParkingError))?;
+    if parking.admin_id != user_id {
+        return Err(reject::custom(error_handler::Error::NoPermissionError));
+    }
+    Ok(parking)
+}
+
+pub async fn get_settings(
+    parking_id: i32,
+    db: Db,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let parking = administered_parking(db_conn, parking_id, user_id)?;
+    Ok(reply::json(&parking.to_settings()))
+}
+
+pub async fn update_settings(
+    parking_id: i32,
+    body: UpdateParkingSettingsRequest,
+    db: Db,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let mut parking = administered_parking(db_conn, parking_id, user_id)?;
+    let changes = body.into_changes();
+    if !changes.is_empty() {
+        parking = diesel::update(parkings::dsl::parkings.find(parking_id))
+            .set(&changes)
+            .get_result::<Parking>(db_conn)
+            .map_err(|_| reject::reject())?;
+    }
+    Ok(reply::json(&parking.to_settings()))
+}
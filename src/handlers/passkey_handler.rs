@@ -0,0 +1,358 @@
+use chrono::Utc;
+use std::ops::Deref;
+use warp::{http::StatusCode, reject, reply, Rejection, Reply};
+
+use crate::db::db_schema::{users, webauthn_credentials};
+use crate::handlers::audit_handler::{record_event, PASSKEY_ADDED, PASSKEY_REMOVED};
+use crate::handlers::error_handler;
+use crate::handlers::guest_handler::forget_recovery_code;
+use crate::handlers::session_handler::open_session;
+use crate::handlers::user_handler::login_reply;
+use crate::models::session::ClientInfo;
+use crate::models::user::User;
+use crate::models::webauthn_credential::WebauthnCredential;
+use crate::routes::Db;
+use crate::security::webauthn::{self, SharedWebauthn, WebauthnError, CHALLENGE_TIMEOUT, SUPPORTED_ALGORITHMS};
+use diesel::*;
+use serde::{Deserialize, Serialize};
+
+const PUBLIC_KEY: &str = "public-key";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyUser {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub alg: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+/// `PublicKeyCredentialCreationOptions` with binary fields base64url encoded.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    pub challenge: String,
+    pub rp: RelyingParty,
+    pub user: PasskeyUser,
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    pub timeout: u128,
+    pub attestation: String,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+}
+
+/// `PublicKeyCredentialRequestOptions` with binary fields base64url encoded.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub timeout: u128,
+    pub user_verification: String,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RegisterPasskeyRequest {
+    pub id: String,
+    pub response: AttestationResponse,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    #[serde(default)]
+    pub user_handle: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyLoginRequest {
+    pub id: String,
+    pub response: AssertionResponse,
+    /// Browser clients ask for the session in a cookie instead of the response body.
+    #[serde(default)]
+    pub cookie: bool,
+}
+
+/// Without a login the options allow any discoverable credential for this relying party.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyLoginOptionsRequest {
+    #[serde(default)]
+    pub login: Option<String>,
+}
+
+fn user_handle(user_id: i32) -> String {
+    webauthn::encode(user_id.to_string().as_bytes())
+}
+
+fn decode_field(value: &str) -> Result<Vec<u8>, Rejection> {
+    webauthn::decode(value).ok_or_else(|| reject::custom(error_handler::Error::InvalidPasskeyError))
+}
+
+fn passkey_rejection(e: WebauthnError) -> Rejection {
+    eprintln!("passkey verification failed: {}", e);
+    reject::custom(error_handler::Error::InvalidPasskeyError)
+}
+
+pub fn credentials_for_user(db_conn: &PgConnection, user_id: i32) -> QueryResult<Vec<WebauthnCredential>> {
+    webauthn_credentials::dsl::webauthn_credentials
+        .filter(webauthn_credentials::dsl::user_id.eq(user_id))
+        .order(webauthn_credentials::dsl::created_at.asc())
+        .load::<WebauthnCredential>(db_conn)
+}
+
+fn descriptors(credentials: &[WebauthnCredential]) -> Vec<CredentialDescriptor> {
+    credentials
+        .iter()
+        .map(|credential| CredentialDescriptor {
+            credential_type: PUBLIC_KEY.to_string(),
+            id: credential.credential_id.clone(),
+        })
+        .collect()
+}
+
+pub async fn registration_options(
+    db: Db,
+    webauthn: SharedWebauthn,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    let user = users::dsl::users
+        .find(user_id)
+        .first::<User>(db_conn)
+        .map_err(|_| reject::custom(error_handler::Error::NoPermissionError))?;
+    let existing = credentials_for_user(db_conn, user_id).map_err(|_| reject::reject())?;
+    let name = user
+        .login
+        .clone()
+        .or_else(|| user.email.clone())
+        .unwrap_or_else(|| format!("user {}", user_id));
+
+    Ok(reply::json(&CreationOptions {
+        challenge: webauthn.registration_challenge(user_id),
+        rp: RelyingParty {
+            id: webauthn.rp_id.clone(),
+            name: webauthn.rp_name.clone(),
+        },
+        user: PasskeyUser {
+            id: user_handle(user_id),
+            display_name: user.display_name.unwrap_or_else(|| name.clone()),
+            name,
+        },
+        pub_key_cred_params: SUPPORTED_ALGORITHMS
+            .iter()
+            .map(|alg| CredentialParameters {
+                credential_type: PUBLIC_KEY.to_string(),
+                alg: *alg,
+            })
+            .collect(),
+        timeout: CHALLENGE_TIMEOUT.as_millis(),
+        attestation: "none".to_string(),
+        exclude_credentials: descriptors(&existing),
+        authenticator_selection: AuthenticatorSelection {
+            resident_key: "preferred".to_string(),
+            user_verification: "preferred".to_string(),
+        },
+    }))
+}
+
+/// Attaching a passkey turns a guest into a regular account that can sign in again later.
+pub async fn register_passkey(
+    body: RegisterPasskeyRequest,
+    db: Db,
+    webauthn: SharedWebauthn,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+    let credential = webauthn
+        .verify_registration(
+            user_id,
+            &decode_field(&body.response.client_data_json)?,
+            &decode_field(&body.response.attestation_object)?,
+        )
+        .map_err(passkey_rejection)?;
+
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let stored = db_conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            let stored = insert_into(webauthn_credentials::dsl::webauthn_credentials)
+                .values((
+                    webauthn_credentials::dsl::credential_id.eq(&credential.credential_id),
+                    webauthn_credentials::dsl::user_id.eq(user_id),
+                    webauthn_credentials::dsl::name.eq(&body.name),
+                    webauthn_credentials::dsl::public_key.eq(&credential.public_key),
+                    webauthn_credentials::dsl::sign_count.eq(credential.sign_count),
+                ))
+                .get_result::<WebauthnCredential>(db_conn)?;
+            diesel::update(users::dsl::users.find(user_id))
+                .set(users::dsl::guest.eq(false))
+                .execute(db_conn)?;
+            forget_recovery_code(db_conn, user_id)?;
+            Ok(stored)
+        })
+        .map_err(|_| reject::custom(error_handler::Error::InvalidPasskeyError))?;
+    record_event(db_conn, Some(user_id), Some(user_id), PASSKEY_ADDED, Some(stored.credential_id.clone()));
+    Ok(reply::with_status(reply::json(&stored), StatusCode::CREATED))
+}
+
+pub async fn list_passkeys(db: Db, user_id: Option<i32>) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    let credentials = credentials_for_user(db_conn, user_id).map_err(|_| reject::reject())?;
+    Ok(reply::json(&credentials))
+}
+
+pub async fn delete_passkey(
+    credential_id: String,
+    db: Db,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    let deleted = diesel::delete(
+        webauthn_credentials::dsl::webauthn_credentials
+            .filter(webauthn_credentials::dsl::credential_id.eq(&credential_id))
+            .filter(webauthn_credentials::dsl::user_id.eq(user_id)),
+    )
+    .execute(db_conn)
+    .map_err(|_| reject::reject())?;
+    if deleted == 0 {
+        return Err(reject::not_found());
+    }
+    record_event(db_conn, Some(user_id), Some(user_id), PASSKEY_REMOVED, Some(credential_id));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn login_options(
+    body: PasskeyLoginOptionsRequest,
+    db: Db,
+    webauthn: SharedWebauthn,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    // Logins that are unknown or have no passkey get a made-up credential instead of an empty
+    // list, so the answer does not tell which logins exist or have passkeys.
+    let allow_credentials = match body.login {
+        Some(user_login) => {
+            let user = users::dsl::users
+                .filter(users::dsl::login.eq(&user_login))
+                .first::<User>(db_conn)
+                .optional()
+                .map_err(|_| reject::reject())?;
+            let credentials = match user {
+                Some(user) => credentials_for_user(db_conn, user.id).map_err(|_| reject::reject())?,
+                None => Vec::new(),
+            };
+            if credentials.is_empty() {
+                vec![CredentialDescriptor {
+                    credential_type: PUBLIC_KEY.to_string(),
+                    id: webauthn.fake_credential_id(&user_login),
+                }]
+            } else {
+                descriptors(&credentials)
+            }
+        }
+        None => Vec::new(),
+    };
+    Ok(reply::json(&RequestOptions {
+        challenge: webauthn.authentication_challenge(),
+        rp_id: webauthn.rp_id.clone(),
+        timeout: CHALLENGE_TIMEOUT.as_millis(),
+        user_verification: "preferred".to_string(),
+        allow_credentials,
+    }))
+}
+
+pub async fn log_in_with_passkey(
+    body: PasskeyLoginRequest,
+    db: Db,
+    webauthn: SharedWebauthn,
+    client: ClientInfo,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let credential = webauthn_credentials::dsl::webauthn_credentials
+        .find(&body.id)
+        .first::<WebauthnCredential>(db_conn)
+        .map_err(|_| reject::custom(error_handler::Error::WrongCredentialsError))?;
+    if let Some(handle) = &body.response.user_handle {
+        if *handle != user_handle(credential.user_id) {
+            return Err(reject::custom(error_handler::Error::WrongCredentialsError));
+        }
+    }
+    let sign_count = webauthn
+        .verify_authentication(
+            &decode_field(&body.response.client_data_json)?,
+            &decode_field(&body.response.authenticator_data)?,
+            &decode_field(&body.response.signature)?,
+            &credential.public_key,
+            credential.sign_count,
+        )
+        .map_err(passkey_rejection)?;
+
+    diesel::update(webauthn_credentials::dsl::webauthn_credentials.find(&credential.credential_id))
+        .set((
+            webauthn_credentials::dsl::sign_count.eq(sign_count),
+            webauthn_credentials::dsl::last_used_at.eq(Some(Utc::now().naive_utc())),
+        ))
+        .execute(db_conn)
+        .map_err(|_| reject::reject())?;
+    let token = open_session(db_conn, credential.user_id, &client).map_err(reject::custom)?;
+    Ok(login_reply(token, body.cookie))
+}
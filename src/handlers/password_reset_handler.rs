@@ -0,0 +1,111 @@
+use chrono::Duration;
+use std::env;
+use std::ops::Deref;
+use warp::{http::StatusCode, reject, Rejection, Reply};
+
+use crate::db::db_schema::users;
+use crate::handlers::audit_handler::{self, record_event};
+use crate::handlers::error_handler;
+use crate::handlers::one_time_token_handler::{
+    consume_token, invalidate_tokens, issue_token, PASSWORD_RESET,
+};
+use crate::handlers::session_handler::revoke_sessions;
+use crate::mail::{send_in_background, Email, SharedMailer};
+use crate::models::session::ClientInfo;
+use crate::models::user::User;
+use crate::routes::Db;
+use crate::security::hash;
+use crate::security::throttle::{AttemptKey, SharedThrottle};
+use diesel::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordResetRequest {
+    pub login: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+fn reset_token_ttl() -> Duration {
+    let minutes = env::var("PASSWORD_RESET_TTL_MINUTES")
+        .ok()
+        .and_then(|m| m.parse().ok())
+        .unwrap_or(30);
+    Duration::minutes(minutes)
+}
+
+fn reset_link(token: &str) -> String {
+    let base = env::var("PASSWORD_RESET_URL").unwrap_or_else(|_| "/password/reset".to_string());
+    format!("{}?token={}", base, token)
+}
+
+/// Replaces any pending reset token of the user and returns the link for the new one.
+pub fn issue_reset_link(db_conn: &PgConnection, user_id: i32) -> QueryResult<String> {
+    invalidate_tokens(db_conn, user_id, PASSWORD_RESET)?;
+    let token = issue_token(db_conn, user_id, PASSWORD_RESET, reset_token_ttl())?;
+    Ok(reset_link(&token))
+}
+
+pub fn mail_reset_link(mailer: &SharedMailer, user_id: i32, login: &str, address: String, link: &str) {
+    let email = Email {
+        to: address,
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Someone asked to reset the password for {}.\n\nOpen {} to choose a new one. If it was not you, ignore this message.",
+            login, link
+        ),
+    };
+    send_in_background(mailer, email, format!("password reset mail for user {}", user_id));
+}
+
+/// Always answers 202 so the response does not reveal which logins exist or have an email;
+/// the mail goes out after the response. Every request counts against the throttle so the
+/// endpoint cannot be used to flood a mailbox.
+pub async fn request_password_reset(
+    body: PasswordResetRequest,
+    db: Db,
+    mailer: SharedMailer,
+    throttle: SharedThrottle,
+    client: ClientInfo,
+) -> Result<impl Reply, Rejection> {
+    let attempt_keys = AttemptKey::PasswordReset(body.login.clone()).with_ip(client.ip);
+    throttle
+        .check(&attempt_keys)
+        .map_err(|retry_after| reject::custom(error_handler::Error::TooManyAttemptsError(retry_after)))?;
+    throttle.record_failure(&attempt_keys);
+
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let user = users::dsl::users
+        .filter(users::dsl::login.eq(&body.login))
+        .first::<User>(db_conn);
+    if let Ok(User { id, email: Some(email), .. }) = user {
+        let link = issue_reset_link(db_conn, id).map_err(|_| reject::reject())?;
+        drop(db_conn_mutex);
+        mail_reset_link(&mailer, id, &body.login, email, &link);
+    }
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn reset_password(body: ResetPasswordRequest, db: Db) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let user_id = consume_token(db_conn, PASSWORD_RESET, &body.token)
+        .ok_or_else(|| reject::custom(error_handler::Error::InvalidTokenError))?;
+    diesel::update(users::dsl::users.find(user_id))
+        .set(users::dsl::password.eq(Some(hash(body.new_password.as_bytes()))))
+        .execute(db_conn)
+        .map_err(|_| reject::reject())?;
+    invalidate_tokens(db_conn, user_id, PASSWORD_RESET).map_err(|_| reject::reject())?;
+    revoke_sessions(db_conn, user_id, None).map_err(|_| reject::reject())?;
+    record_event(db_conn, Some(user_id), Some(user_id), audit_handler::PASSWORD_RESET, None);
+    Ok(StatusCode::OK)
+}
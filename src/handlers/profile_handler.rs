@@ -0,0 +1,219 @@
+use chrono::{NaiveDateTime, Utc};
+use std::ops::Deref;
+use warp::{http::StatusCode, reject, reply, Rejection, Reply};
+
+use crate::db::db_schema::{parkings, parkings_consumers, users};
+use crate::handlers::audit_handler::{events_for_user, record_event, ACCOUNT_DELETED};
+use crate::handlers::email_verification_handler::send_verification_email;
+use crate::handlers::error_handler;
+use crate::handlers::join_request_handler::join_requests_for_user;
+use crate::handlers::membership_handler::memberships_for_user;
+use crate::handlers::mfa_handler::mfa_enabled;
+use crate::handlers::oidc_handler::identities_for_user;
+use crate::handlers::parking_handler::{get_administered_parkings, get_consumed_parkings};
+use crate::handlers::passkey_handler::credentials_for_user;
+use crate::handlers::session_handler::sessions_for_user;
+use crate::mail::SharedMailer;
+use crate::models::audit_entry::AuditEntry;
+use crate::models::join_request::JoinRequest;
+use crate::models::oidc_identity::OidcIdentity;
+use crate::models::parking_consumer::ParkingConsumer;
+use crate::models::session::Session;
+use crate::models::user::{
+    is_valid_locale, is_valid_time_zone, normalize_email, Profile, UpdateProfileRequest, User,
+};
+use crate::models::webauthn_credential::WebauthnCredential;
+use crate::routes::Db;
+use diesel::*;
+use serde::{Deserialize, Serialize};
+
+fn load_profile(db_conn: &PgConnection, user_id: i32) -> Result<Profile, Rejection> {
+    let user = users::dsl::users
+        .find(user_id)
+        .first::<User>(db_conn)
+        .map_err(|_| reject::custom(error_handler::Error::NoPermissionError))?;
+    let administered_parkings = get_administered_parkings(db_conn, user_id)
+        .map_err(|_| reject::reject())?
+        .iter()
+        .map(|parking| parking.to_parking_without_password())
+        .collect();
+    let consumed_parkings = get_consumed_parkings(db_conn, user_id)
+        .iter()
+        .map(|parking| parking.to_parking_without_password())
+        .collect();
+    Ok(Profile {
+        id: user.id,
+        guest: user.is_guest(),
+        email_verified: user.has_verified_email(),
+        mfa_enabled: mfa_enabled(db_conn, user_id).map_err(|_| reject::reject())?,
+        login: user.login,
+        display_name: user.display_name,
+        email: user.email,
+        locale: user.locale,
+        time_zone: user.time_zone,
+        administered_parkings,
+        consumed_parkings,
+    })
+}
+
+pub async fn get_profile(db: Db, user_id: Option<i32>) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    Ok(reply::json(&load_profile(db_conn, user_id)?))
+}
+
+/// Changing the email address marks it unverified and sends a verification link to the new one.
+pub async fn update_profile(
+    body: UpdateProfileRequest,
+    db: Db,
+    mailer: SharedMailer,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    let current_email = users::dsl::users
+        .find(user_id)
+        .select(users::dsl::email)
+        .first::<Option<String>>(db_conn)
+        .map_err(|_| reject::custom(error_handler::Error::NoPermissionError))?;
+    let mut changes = body.into_changes();
+    if let Some(Some(new_email)) = changes.email.as_mut() {
+        *new_email = normalize_email(new_email).ok_or_else(|| reject::custom(error_handler::Error::InvalidEmailError))?;
+    }
+    if let Some(Some(locale)) = &changes.locale {
+        if !is_valid_locale(locale) {
+            return Err(reject::custom(error_handler::Error::InvalidLocaleError));
+        }
+    }
+    if let Some(Some(time_zone)) = &changes.time_zone {
+        if !is_valid_time_zone(time_zone) {
+            return Err(reject::custom(error_handler::Error::InvalidTimeZoneError));
+        }
+    }
+    if changes.email.as_ref() == Some(&current_email) {
+        changes.email = None;
+    } else if changes.email.is_some() {
+        changes.email_verified = Some(false);
+    }
+    if let Some(Some(new_email)) = &changes.email {
+        let taken = users::dsl::users
+            .filter(users::dsl::email.eq(new_email))
+            .filter(users::dsl::user_id.ne(user_id))
+            .first::<User>(db_conn)
+            .is_ok();
+        if taken {
+            return Err(reject::custom(error_handler::Error::EmailInUseError));
+        }
+    }
+    if !changes.is_empty() {
+        diesel::update(users::dsl::users.find(user_id))
+            .set(&changes)
+            .execute(db_conn)
+            .map_err(|_| reject::reject())?;
+    }
+    if let Some(Some(new_email)) = &changes.email {
+        // the new address is already saved; the user can ask for another link
+        if let Err(e) = send_verification_email(db_conn, &mailer, user_id, new_email) {
+            eprintln!("verification link for user {} not issued: {}", user_id, e);
+        }
+    }
+
+    Ok(reply::json(&load_profile(db_conn, user_id)?))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteAccountQuery {
+    #[serde(default)]
+    pub cascade: bool,
+}
+
+/// Refuses to delete admins of parkings unless `cascade` is set, in which case those parkings go too.
+pub async fn delete_account(
+    query: DeleteAccountQuery,
+    db: Db,
+    user_id: Option<i32>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    let owned_parking_ids: Vec<i32> = get_administered_parkings(db_conn, user_id)
+        .map_err(|_| reject::reject())?
+        .iter()
+        .map(|parking| parking.parking_id)
+        .collect();
+    if !owned_parking_ids.is_empty() && !query.cascade {
+        return Err(reject::custom(error_handler::Error::OwnedParkingsError));
+    }
+
+    db_conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            diesel::delete(
+                parkings_consumers::dsl::parkings_consumers
+                    .filter(parkings_consumers::dsl::parking_id.eq_any(&owned_parking_ids)),
+            )
+            .execute(db_conn)?;
+            diesel::delete(
+                parkings::dsl::parkings.filter(parkings::dsl::parking_id.eq_any(&owned_parking_ids)),
+            )
+            .execute(db_conn)?;
+            diesel::delete(
+                parkings_consumers::dsl::parkings_consumers
+                    .filter(parkings_consumers::dsl::consumer_id.eq(user_id)),
+            )
+            .execute(db_conn)?;
+            // actor and subject would be nulled by the foreign keys, so the id goes in the details
+            record_event(
+                db_conn,
+                None,
+                None,
+                ACCOUNT_DELETED,
+                Some(format!("user {}, {} administered parkings deleted", user_id, owned_parking_ids.len())),
+            );
+            diesel::delete(users::dsl::users.find(user_id)).execute(db_conn)?;
+            Ok(())
+        })
+        .map_err(|_| reject::reject())?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountExport {
+    pub exported_at: NaiveDateTime,
+    pub profile: Profile,
+    /// Membership rows with their expiry dates; `profile` lists the parkings themselves.
+    pub memberships: Vec<ParkingConsumer>,
+    pub join_requests: Vec<JoinRequest>,
+    pub sessions: Vec<Session>,
+    pub passkeys: Vec<WebauthnCredential>,
+    pub linked_identities: Vec<OidcIdentity>,
+    pub audit_entries: Vec<AuditEntry>,
+}
+
+pub async fn export_account(db: Db, user_id: Option<i32>) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let user_id = user_id.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    let export = AccountExport {
+        exported_at: Utc::now().naive_utc(),
+        profile: load_profile(db_conn, user_id)?,
+        memberships: memberships_for_user(db_conn, user_id).map_err(|_| reject::reject())?,
+        join_requests: join_requests_for_user(db_conn, user_id).map_err(|_| reject::reject())?,
+        sessions: sessions_for_user(db_conn, user_id).map_err(|_| reject::reject())?,
+        passkeys: credentials_for_user(db_conn, user_id).map_err(|_| reject::reject())?,
+        linked_identities: identities_for_user(db_conn, user_id).map_err(|_| reject::reject())?,
+        audit_entries: events_for_user(db_conn, user_id).map_err(|_| reject::reject())?,
+    };
+    Ok(reply::with_header(
+        reply::json(&export),
+        "content-disposition",
+        "attachment; filename=\"account-export.json\"",
+    ))
+}
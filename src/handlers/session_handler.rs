@@ -0,0 +1,160 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::*;
+use std::ops::Deref;
+use warp::{http::StatusCode, reject, reply, Rejection, Reply};
+
+use crate::db::db_schema::{sessions, users};
+use crate::handlers::audit_handler::{record_event, SESSION_REVOKED};
+use crate::handlers::error_handler;
+use crate::handlers::error_handler::Error;
+use crate::models::session::{ClientInfo, Session, SessionInfo};
+use crate::routes::Db;
+use crate::security::cookie::add_expired_cookies;
+use crate::security::{create_impersonation_jwt, create_jwt, decode_jwt, Claims};
+
+/// How stale `last_seen_at` may get before a request refreshes it, to avoid a write per request.
+fn last_seen_resolution() -> Duration {
+    Duration::minutes(1)
+}
+
+/// Disabled accounts cannot sign in, and `authorize` refuses the sessions they already hold.
+pub fn ensure_enabled(db_conn: &PgConnection, user_id: i32) -> Result<(), Error> {
+    let disabled_at = users::dsl::users
+        .find(user_id)
+        .select(users::dsl::disabled_at)
+        .first::<Option<NaiveDateTime>>(db_conn)
+        .map_err(|_| Error::JWTTokenError)?;
+    match disabled_at {
+        Some(_) => Err(Error::AccountDisabledError),
+        None => Ok(()),
+    }
+}
+
+pub fn open_session(db_conn: &PgConnection, user_id: i32, client: &ClientInfo) -> Result<String, Error> {
+    ensure_enabled(db_conn, user_id)?;
+    let session_id = insert_into(sessions::dsl::sessions)
+        .values((
+            sessions::dsl::user_id.eq(user_id),
+            sessions::dsl::device_name.eq(client.device_name()),
+            sessions::dsl::ip_address.eq(client.ip.map(|ip| ip.to_string())),
+        ))
+        .returning(sessions::dsl::session_id)
+        .get_result::<i32>(db_conn)
+        .map_err(|_| Error::JWTTokenCreationError)?;
+    create_jwt(&user_id, &session_id)
+}
+
+/// A session for `user_id` used by the system admin `actor_id`; it shows up in the user's
+/// session list and expires with its short-lived token.
+pub fn open_impersonation_session(
+    db_conn: &PgConnection,
+    user_id: i32,
+    actor_id: i32,
+    read_only: bool,
+    lifetime: Duration,
+    client: &ClientInfo,
+) -> Result<String, Error> {
+    ensure_enabled(db_conn, user_id)?;
+    let session_id = insert_into(sessions::dsl::sessions)
+        .values((
+            sessions::dsl::user_id.eq(user_id),
+            sessions::dsl::device_name.eq(client.device_name()),
+            sessions::dsl::ip_address.eq(client.ip.map(|ip| ip.to_string())),
+            sessions::dsl::impersonator_id.eq(Some(actor_id)),
+        ))
+        .returning(sessions::dsl::session_id)
+        .get_result::<i32>(db_conn)
+        .map_err(|_| Error::JWTTokenCreationError)?;
+    create_impersonation_jwt(user_id, session_id, actor_id, read_only, lifetime)
+}
+
+pub fn revoke_sessions(
+    db_conn: &PgConnection,
+    user_id: i32,
+    keep_session_id: Option<i32>,
+) -> QueryResult<usize> {
+    diesel::update(
+        sessions::dsl::sessions
+            .filter(sessions::dsl::user_id.eq(user_id))
+            .filter(sessions::dsl::session_id.ne(keep_session_id.unwrap_or(-1)))
+            .filter(sessions::dsl::revoked_at.is_null()),
+    )
+    .set(sessions::dsl::revoked_at.eq(Some(Utc::now().naive_utc())))
+    .execute(db_conn)
+}
+
+pub fn sessions_for_user(db_conn: &PgConnection, user_id: i32) -> QueryResult<Vec<Session>> {
+    sessions::dsl::sessions
+        .filter(sessions::dsl::user_id.eq(user_id))
+        .order(sessions::dsl::created_at.desc())
+        .load::<Session>(db_conn)
+}
+
+/// Checks the token signature and expiry and that its session has not been revoked.
+pub fn validate_token(db_conn: &PgConnection, token: &str) -> Result<Claims, Error> {
+    let claims = decode_jwt(token)?;
+    let session = sessions::dsl::sessions
+        .find(claims.sid)
+        .filter(sessions::dsl::user_id.eq(claims.id))
+        .filter(sessions::dsl::revoked_at.is_null())
+        .first::<Session>(db_conn)
+        .map_err(|_| Error::JWTTokenError)?;
+    let now = Utc::now().naive_utc();
+    if now - session.last_seen_at > last_seen_resolution() {
+        if let Err(e) = diesel::update(sessions::dsl::sessions.find(session.session_id))
+            .set(sessions::dsl::last_seen_at.eq(now))
+            .execute(db_conn)
+        {
+            eprintln!("last seen time of session {} not updated: {:?}", session.session_id, e);
+        }
+    }
+    Ok(claims)
+}
+
+pub async fn list_sessions(db: Db, claims: Option<Claims>) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let claims = claims.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    let sessions: Vec<SessionInfo> = sessions::dsl::sessions
+        .filter(sessions::dsl::user_id.eq(claims.id))
+        .filter(sessions::dsl::revoked_at.is_null())
+        .order(sessions::dsl::last_seen_at.desc())
+        .load::<Session>(db_conn)
+        .map_err(|_| reject::reject())?
+        .iter()
+        .map(|session| session.to_session_info(claims.sid))
+        .collect();
+    Ok(reply::json(&sessions))
+}
+
+/// Signs out one device; revoking the current session is allowed and works like a logout,
+/// including clearing the session cookie of browser clients.
+pub async fn revoke_session(
+    session_id: i32,
+    db: Db,
+    claims: Option<Claims>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let claims = claims.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    let revoked = diesel::update(
+        sessions::dsl::sessions
+            .find(session_id)
+            .filter(sessions::dsl::user_id.eq(claims.id))
+            .filter(sessions::dsl::revoked_at.is_null()),
+    )
+    .set(sessions::dsl::revoked_at.eq(Some(Utc::now().naive_utc())))
+    .execute(db_conn)
+    .map_err(|_| reject::reject())?;
+    if revoked == 0 {
+        return Err(reject::not_found());
+    }
+    record_event(db_conn, Some(claims.id), Some(claims.id), SESSION_REVOKED, Some(session_id.to_string()));
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    if session_id == claims.sid {
+        add_expired_cookies(&mut response);
+    }
+    Ok(response)
+}
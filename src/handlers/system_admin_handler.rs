@@ -0,0 +1,298 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use std::env;
+use std::ops::Deref;
+use warp::{http::StatusCode, reject, reply, Rejection, Reply};
+
+use crate::db::contains_pattern;
+use crate::db::db_schema::{parkings, parkings_consumers, sessions, users};
+use crate::handlers::audit_handler::{
+    record_event, ACCOUNT_DISABLED, ACCOUNT_ENABLED, IMPERSONATION_STARTED, PARKING_REASSIGNED,
+    PASSWORD_RESET_FORCED, SYSTEM_ADMIN_GRANTED, SYSTEM_ADMIN_REVOKED,
+};
+use crate::handlers::error_handler;
+use crate::handlers::parking_handler::get_memberships;
+use crate::handlers::password_reset_handler::{issue_reset_link, mail_reset_link};
+use crate::handlers::session_handler::{open_impersonation_session, revoke_sessions};
+use crate::mail::SharedMailer;
+use crate::models::parking::{Membership, Parking};
+use crate::models::session::ClientInfo;
+use crate::models::user::{User, UserSummary};
+use crate::routes::Db;
+use diesel::*;
+use serde::{Deserialize, Serialize};
+
+const MAX_SEARCH_RESULTS: i64 = 200;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSearchQuery {
+    /// Matched against login, email and display name; a number also matches the user id.
+    #[serde(default)]
+    pub q: String,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UserDetails {
+    pub user: UserSummary,
+    pub memberships: Vec<Membership>,
+    pub active_sessions: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ForcedPasswordReset {
+    pub emailed: bool,
+    /// Only returned when the user has no email address, for support to pass on.
+    pub reset_link: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImpersonationRequest {
+    /// Tokens are read-only unless this is set.
+    #[serde(default)]
+    pub read_write: bool,
+    /// Why support needs to look; kept in the audit log.
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImpersonationResponse {
+    pub token: String,
+    pub expires_at: NaiveDateTime,
+    pub read_only: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReassignParkingRequest {
+    pub admin_id: i32,
+}
+
+fn impersonation_ttl() -> Duration {
+    let minutes = env::var("IMPERSONATION_TTL_MINUTES")
+        .ok()
+        .and_then(|m| m.parse().ok())
+        .unwrap_or(15);
+    Duration::minutes(minutes)
+}
+
+fn find_user(db_conn: &PgConnection, user_id: i32) -> Result<User, Rejection> {
+    users::dsl::users
+        .find(user_id)
+        .first::<User>(db_conn)
+        .map_err(|_| reject::not_found())
+}
+
+pub async fn search_users(
+    query: UserSearchQuery,
+    db: Db,
+    _admin_id: i32,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let term = query.q.trim();
+    let mut search = users::dsl::users.into_boxed();
+    if !term.is_empty() {
+        let pattern = contains_pattern(term);
+        let id = term.parse::<i32>().unwrap_or(-1);
+        search = search.filter(
+            users::dsl::user_id
+                .eq(id)
+                .or(users::dsl::login.ilike(pattern.clone()))
+                .or(users::dsl::email.ilike(pattern.clone()))
+                .or(users::dsl::display_name.ilike(pattern)),
+        );
+    }
+    let users = search
+        .order(users::dsl::user_id)
+        .limit(query.limit.unwrap_or(50).clamp(1, MAX_SEARCH_RESULTS))
+        .load::<User>(db_conn)
+        .map_err(|_| reject::reject())?;
+    let summaries: Vec<UserSummary> = users.iter().map(|user| user.to_summary()).collect();
+    Ok(reply::json(&summaries))
+}
+
+pub async fn get_user(user_id: i32, db: Db, _admin_id: i32) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let user = find_user(db_conn, user_id)?;
+    let active_sessions = sessions::dsl::sessions
+        .filter(sessions::dsl::user_id.eq(user_id))
+        .filter(sessions::dsl::revoked_at.is_null())
+        .count()
+        .get_result::<i64>(db_conn)
+        .map_err(|_| reject::reject())?;
+    Ok(reply::json(&UserDetails {
+        user: user.to_summary(),
+        memberships: get_memberships(db_conn, user_id).map_err(|_| reject::reject())?,
+        active_sessions,
+    }))
+}
+
+/// Signs the user out everywhere and keeps them out until the account is enabled again.
+pub async fn disable_user(user_id: i32, db: Db, admin_id: i32) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    if user_id == admin_id {
+        return Err(reject::custom(error_handler::Error::NoPermissionError));
+    }
+    let user = find_user(db_conn, user_id)?;
+    if !user.is_disabled() {
+        diesel::update(users::dsl::users.find(user_id))
+            .set(users::dsl::disabled_at.eq(Some(Utc::now().naive_utc())))
+            .execute(db_conn)
+            .map_err(|_| reject::reject())?;
+        revoke_sessions(db_conn, user_id, None).map_err(|_| reject::reject())?;
+        record_event(db_conn, Some(admin_id), Some(user_id), ACCOUNT_DISABLED, None);
+    }
+    Ok(reply::json(&find_user(db_conn, user_id)?.to_summary()))
+}
+
+pub async fn enable_user(user_id: i32, db: Db, admin_id: i32) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let user = find_user(db_conn, user_id)?;
+    if user.is_disabled() {
+        diesel::update(users::dsl::users.find(user_id))
+            .set(users::dsl::disabled_at.eq::<Option<NaiveDateTime>>(None))
+            .execute(db_conn)
+            .map_err(|_| reject::reject())?;
+        record_event(db_conn, Some(admin_id), Some(user_id), ACCOUNT_ENABLED, None);
+    }
+    Ok(reply::json(&find_user(db_conn, user_id)?.to_summary()))
+}
+
+/// Clears the current password and signs the user out, so the reset link is the only way back
+/// in with a password. The link is mailed when the user has an address.
+pub async fn force_password_reset(
+    user_id: i32,
+    db: Db,
+    mailer: SharedMailer,
+    admin_id: i32,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let user = find_user(db_conn, user_id)?;
+    diesel::update(users::dsl::users.find(user_id))
+        .set(users::dsl::password.eq::<Option<String>>(None))
+        .execute(db_conn)
+        .map_err(|_| reject::reject())?;
+    revoke_sessions(db_conn, user_id, None).map_err(|_| reject::reject())?;
+    let link = issue_reset_link(db_conn, user_id).map_err(|_| reject::reject())?;
+    let emailed = user.email.is_some();
+    record_event(
+        db_conn,
+        Some(admin_id),
+        Some(user_id),
+        PASSWORD_RESET_FORCED,
+        Some(if emailed { "link mailed" } else { "link handed to admin" }.to_string()),
+    );
+    let login = user.login.clone().unwrap_or_else(|| format!("user {}", user_id));
+    match user.email {
+        Some(address) => {
+            mail_reset_link(&mailer, user_id, &login, address, &link);
+            Ok(reply::json(&ForcedPasswordReset {
+                emailed: true,
+                reset_link: None,
+            }))
+        }
+        None => Ok(reply::json(&ForcedPasswordReset {
+            emailed: false,
+            reset_link: Some(link),
+        })),
+    }
+}
+
+/// Issues a token to see the service as the user does. Other system admins cannot be
+/// impersonated, so the token never reaches the admin routes.
+pub async fn impersonate_user(
+    user_id: i32,
+    body: ImpersonationRequest,
+    db: Db,
+    client: ClientInfo,
+    admin_id: i32,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let user = find_user(db_conn, user_id)?;
+    if user.id == admin_id || user.is_system_admin() {
+        return Err(reject::custom(error_handler::Error::NoPermissionError));
+    }
+    let read_only = !body.read_write;
+    let lifetime = impersonation_ttl();
+    let token = open_impersonation_session(db_conn, user_id, admin_id, read_only, lifetime, &client)
+        .map_err(reject::custom)?;
+    let mode = if read_only { "read-only" } else { "read-write" };
+    let details = match body.reason {
+        Some(reason) => format!("{}: {}", mode, reason),
+        None => mode.to_string(),
+    };
+    record_event(db_conn, Some(admin_id), Some(user_id), IMPERSONATION_STARTED, Some(details));
+    Ok(reply::json(&ImpersonationResponse {
+        token,
+        expires_at: Utc::now().naive_utc() + lifetime,
+        read_only,
+    }))
+}
+
+/// Takes over a parking whose admin is gone or unreachable; the previous admin keeps no role in it.
+pub async fn reassign_parking(
+    parking_id: i32,
+    body: ReassignParkingRequest,
+    db: Db,
+    admin_id: i32,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+
+    let parking = parkings::dsl::parkings
+        .find(parking_id)
+        .first::<Parking>(db_conn)
+        .map_err(|_| reject::custom(error_handler::Error::WrongParkingError))?;
+    let new_admin = find_user(db_conn, body.admin_id)?;
+    if new_admin.is_guest() || new_admin.is_disabled() {
+        return Err(reject::custom(error_handler::Error::NoPermissionError));
+    }
+
+    db_conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            diesel::delete(parkings_consumers::dsl::parkings_consumers.find((parking_id, new_admin.id)))
+                .execute(db_conn)?;
+            diesel::update(parkings::dsl::parkings.find(parking_id))
+                .set(parkings::dsl::admin_id.eq(new_admin.id))
+                .execute(db_conn)?;
+            Ok(())
+        })
+        .map_err(|_| reject::reject())?;
+    record_event(
+        db_conn,
+        Some(admin_id),
+        Some(new_admin.id),
+        PARKING_REASSIGNED,
+        Some(format!("parking {} from user {}", parking_id, parking.admin_id)),
+    );
+    Ok(StatusCode::OK)
+}
+
+/// Grants or revokes the system admin role by login; used by the CLI, so there is no acting user.
+pub fn set_system_admin(db_conn: &PgConnection, login: &str, system_admin: bool) -> QueryResult<usize> {
+    let user_ids = diesel::update(users::dsl::users.filter(users::dsl::login.eq(login)))
+        .set(users::dsl::system_admin.eq(system_admin))
+        .returning(users::dsl::user_id)
+        .get_results::<i32>(db_conn)?;
+    let action = if system_admin { SYSTEM_ADMIN_GRANTED } else { SYSTEM_ADMIN_REVOKED };
+    for user_id in &user_ids {
+        record_event(db_conn, None, Some(*user_id), action, None);
+    }
+    Ok(user_ids.len())
+}
@@ -1,20 +1,30 @@
-use warp::{http::StatusCode, reject, reply, Rejection, Reply};
+use warp::{http::StatusCode, reject, reply, reply::Response, Rejection, Reply};
 
 
 use crate::routes::Db;
 
 use crate::db::db_schema::users;
 use crate::handlers::error_handler;
-use crate::handlers::error_handler::Error::LoginInUseError;
-use crate::models::user::{User, UserCredentials};
+use crate::handlers::error_handler::Error::{EmailInUseError, InvalidEmailError, LoginInUseError};
+use crate::mail::SharedMailer;
+use crate::models::session::ClientInfo;
+use crate::models::user::{normalize_email, RegisterRequest, User, UserCredentials};
 use diesel::result::Error;
 use diesel::*;
 use serde::{Deserialize, Serialize};
 use std::ops::Deref;
-use crate::db::db_schema::users::dsl::{login, password};
+use crate::db::db_schema::users::dsl::{email, login, password};
 use diesel::expression::bound::Bound;
 use diesel::sql_types::Text;
-use crate::security::{hash, create_jwt, verify};
+use crate::handlers::audit_handler::{record_event, PASSWORD_CHANGED};
+use crate::handlers::email_verification_handler::send_verification_email;
+use crate::handlers::guest_handler::forget_recovery_code;
+use crate::handlers::mfa_handler::{mfa_challenge_ttl, mfa_enabled};
+use crate::handlers::one_time_token_handler::{issue_token, MFA_CHALLENGE};
+use crate::handlers::session_handler::{open_session, revoke_sessions};
+use crate::security::throttle::{AttemptKey, SharedThrottle};
+use crate::security::cookie::add_session_cookies;
+use crate::security::{check_password, generate_token, hash, verify, verify_dummy, Claims, PasswordCheck};
 
 fn find_user_by_login(db_conn: &PgConnection, user_login: String) -> Result<User, Error> {
     users::dsl::users
@@ -24,30 +34,57 @@ fn find_user_by_login(db_conn: &PgConnection, user_login: String) -> Result<User
 
 type UserUpdateCredentials = (
     diesel::expression::operators::Eq<login, Bound<diesel::sql_types::Nullable<Text>, Option<String>>>,
-    diesel::expression::operators::Eq<password, Bound<diesel::sql_types::Nullable<Text>, Option<String>>>
+    diesel::expression::operators::Eq<password, Bound<diesel::sql_types::Nullable<Text>, Option<String>>>,
+    diesel::expression::operators::Eq<email, Bound<diesel::sql_types::Nullable<Text>, Option<String>>>
 );
+/// A provided email address gets a verification link once the account is registered.
 pub async fn register(
-    new_user: UserCredentials,
+    new_user: RegisterRequest,
     db: Db,
+    mailer: SharedMailer,
     user_id: Option<i32>,
 ) -> Result<impl Reply, Rejection> {
     let db_conn_mutex = db.lock().unwrap();
     let db_conn = db_conn_mutex.deref();
 
+    let new_email = match new_user.email.as_deref().map(str::trim).filter(|e| !e.is_empty()) {
+        Some(address) => Some(normalize_email(address).ok_or_else(|| reject::custom(InvalidEmailError))?),
+        None => None,
+    };
     let user_by_name = find_user_by_login(db_conn, new_user.login.clone());
+    let registered_login = new_user.login.clone();
     let hashed_password = Some(hash(new_user.password.as_bytes()));
     let new_credentials = (
         users::dsl::login.eq(Some(new_user.login)),
         users::dsl::password.eq(hashed_password),
+        users::dsl::email.eq(new_email.clone()),
     );
     match user_by_name {
         Ok(_) => return Err(reject::custom(LoginInUseError)),
         Err(_) => {}
     };
-    match user_id {
+    if let Some(new_email) = &new_email {
+        if users::dsl::users
+            .filter(users::dsl::email.eq(new_email))
+            .first::<User>(db_conn)
+            .is_ok()
+        {
+            return Err(reject::custom(EmailInUseError));
+        }
+    }
+    let status = match user_id {
         None => create_user(db_conn,new_credentials),
         Some(id) => update_user(id, db_conn, new_credentials)
+    }?;
+    if let (StatusCode::CREATED, Some(address)) = (status, &new_email) {
+        if let Ok(user) = find_user_by_login(db_conn, registered_login) {
+            // the account exists by now, so a missing link must not fail the registration
+            if let Err(e) = send_verification_email(db_conn, &mailer, user.id, address) {
+                eprintln!("verification link for user {} not issued: {}", user.id, e);
+            }
+        }
     }
+    Ok(status)
 }
 fn create_user(
     db_conn:&PgConnection,
@@ -71,10 +108,16 @@ fn update_user(
     let user_to_update: Result<User, Error> = target.first::<User>(db_conn);
     match user_to_update {
         Ok(user) => {
-            if user.login.is_none() || user.password.is_none() {
-                diesel::update(target)
-                    .set(new_credentials.clone())
-                    .execute(db_conn);
+            if !user.has_local_credentials() {
+                db_conn
+                    .transaction::<_, Error, _>(|| {
+                        diesel::update(target)
+                            .set((new_credentials.clone(), users::dsl::guest.eq(false)))
+                            .execute(db_conn)?;
+                        forget_recovery_code(db_conn, id)?;
+                        Ok(())
+                    })
+                    .map_err(|_| reject::reject())?;
                 Ok(StatusCode::CREATED)
             } else {
                 Ok(StatusCode::UNAUTHORIZED)
@@ -90,25 +133,154 @@ pub struct LoginResponse {
     pub token: String,
 }
 
+/// Returned to cookie sessions; the front end sends `csrfToken` in the `X-CSRF-Token` header.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CookieLoginResponse {
+    pub csrf_token: String,
+}
+
+/// A login response with more fields next to the token.
+#[derive(Serialize)]
+struct ExtendedLoginResponse<'a, R, T> {
+    #[serde(flatten)]
+    login: R,
+    #[serde(flatten)]
+    extra: &'a T,
+}
+
+/// Hands a new session token to the client, in the body or as an HttpOnly cookie.
+pub fn login_reply(token: String, cookie: bool) -> Response {
+    login_reply_with(token, cookie, &())
+}
+
+/// Like `login_reply`, with the fields of `extra` added to the body.
+pub fn login_reply_with<T: Serialize>(token: String, cookie: bool, extra: &T) -> Response {
+    if !cookie {
+        return reply::json(&ExtendedLoginResponse { login: LoginResponse { token }, extra }).into_response();
+    }
+    let csrf_token = generate_token();
+    let mut response = reply::json(&ExtendedLoginResponse {
+        login: CookieLoginResponse {
+            csrf_token: csrf_token.clone(),
+        },
+        extra,
+    })
+    .into_response();
+    add_session_cookies(&mut response, &token, &csrf_token);
+    response
+}
+
+/// Returned instead of a token when the account has two-factor authentication enabled.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MfaChallengeResponse {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+}
+
+/// Finishes a login whose first factor succeeded: accounts with two-factor authentication
+/// get an MFA challenge, everyone else a session token.
+pub fn finish_first_factor(
+    db_conn: &PgConnection,
+    user_id: i32,
+    client: &ClientInfo,
+    cookie: bool,
+) -> Result<Response, Rejection> {
+    if mfa_enabled(db_conn, user_id).map_err(|_| reject::reject())? {
+        let mfa_token = issue_token(db_conn, user_id, MFA_CHALLENGE, mfa_challenge_ttl())
+            .map_err(|_| reject::reject())?;
+        return Ok(reply::json(&MfaChallengeResponse {
+            mfa_required: true,
+            mfa_token,
+        })
+        .into_response());
+    }
+    let token = open_session(db_conn, user_id, client).map_err(reject::custom)?;
+    Ok(login_reply(token, cookie))
+}
+
 pub async fn log_in(
     credentials: UserCredentials,
     db: Db,
-    jwt_secret: String,
+    throttle: SharedThrottle,
+    client: ClientInfo,
 ) -> Result<impl Reply, Rejection> {
+    let account_key = AttemptKey::Login(credentials.login.clone());
+    let attempt_keys = account_key.clone().with_ip(client.ip);
+    throttle
+        .check(&attempt_keys)
+        .map_err(|retry_after| reject::custom(error_handler::Error::TooManyAttemptsError(retry_after)))?;
+
     let db_conn_mutex = db.lock().unwrap();
     let db_conn = db_conn_mutex.deref();
-    let users_by_name = find_user_by_login(db_conn, credentials.login.clone());
-
-    match users_by_name {
-        Ok(found_users) => {
-            let user_password = found_users.password.clone().unwrap();
-            if verify(&user_password, credentials.password.as_bytes()) {
-                let token = create_jwt(&found_users.id, jwt_secret.as_bytes()).unwrap();
-                Ok(reply::json(&LoginResponse { token }))
-            } else {
-                Err(reject::custom(error_handler::Error::WrongCredentialsError))
+    let user = find_user_by_login(db_conn, credentials.login.clone()).ok();
+    let check = match user.as_ref().and_then(|u| u.password.as_ref()) {
+        Some(user_password) => check_password(user_password, credentials.password.as_bytes()),
+        None => {
+            verify_dummy(credentials.password.as_bytes());
+            PasswordCheck::Invalid
+        }
+    };
+
+    match user {
+        Some(found_user) if check.is_valid() => {
+            throttle.record_success(&[account_key]);
+            if check == PasswordCheck::Outdated {
+                rehash_password(db_conn, found_user.id, &credentials.password);
             }
+            finish_first_factor(db_conn, found_user.id, &client, credentials.cookie)
+        }
+        _ => {
+            throttle.record_failure(&attempt_keys);
+            Err(reject::custom(error_handler::Error::WrongCredentialsError))
         }
-        _ => Err(reject::custom(error_handler::Error::WrongCredentialsError)),
     }
 }
+
+/// Upgrades a hash made with outdated parameters; failures only mean another try on the next login.
+fn rehash_password(db_conn: &PgConnection, id: i32, plain_password: &str) {
+    let result = diesel::update(users::dsl::users.find(id))
+        .set(users::dsl::password.eq(Some(hash(plain_password.as_bytes()))))
+        .execute(db_conn);
+    if let Err(e) = result {
+        eprintln!("password of user {} not rehashed: {:?}", id, e);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+pub async fn change_password(
+    body: ChangePasswordRequest,
+    db: Db,
+    claims: Option<Claims>,
+) -> Result<impl Reply, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    let db_conn = db_conn_mutex.deref();
+    let claims = claims.ok_or_else(|| reject::custom(error_handler::Error::NoPermissionError))?;
+
+    let user = users::dsl::users
+        .find(claims.id)
+        .first::<User>(db_conn)
+        .map_err(|_| reject::custom(error_handler::Error::NoPermissionError))?;
+    let current_hash = match user.password {
+        Some(current_hash) => current_hash,
+        None => return Err(reject::custom(error_handler::Error::NoPermissionError)),
+    };
+    if !verify(&current_hash, body.current_password.as_bytes()) {
+        return Err(reject::custom(error_handler::Error::WrongCredentialsError));
+    }
+
+    diesel::update(users::dsl::users.find(user.id))
+        .set(users::dsl::password.eq(Some(hash(body.new_password.as_bytes()))))
+        .execute(db_conn)
+        .map_err(|_| reject::reject())?;
+    revoke_sessions(db_conn, user.id, Some(claims.sid)).map_err(|_| reject::reject())?;
+    record_event(db_conn, Some(user.id), Some(user.id), PASSWORD_CHANGED, None);
+    Ok(StatusCode::OK)
+}
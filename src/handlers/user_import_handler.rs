@@ -0,0 +1,93 @@
+use diesel::*;
+use serde::{Deserialize, Serialize};
+
+use crate::db::db_schema::users;
+use crate::handlers::audit_handler::{record_event, USER_IMPORTED};
+use crate::models::user::{normalize_email, User};
+use crate::security::password::is_supported_hash;
+
+/// One account from another system. The password hash may be argon2, bcrypt or PBKDF2
+/// and is upgraded to our argon2 parameters on the first successful login.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedUser {
+    pub login: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(alias = "password_hash")]
+    pub password_hash: String,
+    #[serde(default, alias = "display_name")]
+    pub display_name: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub imported: usize,
+    /// Login and reason for every record that was left out.
+    pub skipped: Vec<(String, String)>,
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+fn skip_reason(db_conn: &PgConnection, user: &ImportedUser) -> QueryResult<Option<&'static str>> {
+    if user.login.trim().is_empty() {
+        return Ok(Some("empty login"));
+    }
+    if !is_supported_hash(&user.password_hash) {
+        return Ok(Some("unsupported password hash"));
+    }
+    let login_taken = users::dsl::users
+        .filter(users::dsl::login.eq(&user.login))
+        .first::<User>(db_conn)
+        .optional()?
+        .is_some();
+    if login_taken {
+        return Ok(Some("login already exists"));
+    }
+    if let Some(email) = &user.email {
+        let email_taken = users::dsl::users
+            .filter(users::dsl::email.eq(email))
+            .first::<User>(db_conn)
+            .optional()?
+            .is_some();
+        if email_taken {
+            return Ok(Some("email already in use"));
+        }
+    }
+    Ok(None)
+}
+
+/// Inserts all acceptable records in one transaction; invalid or conflicting ones are reported, not fatal.
+pub fn import_users(db_conn: &PgConnection, records: Vec<ImportedUser>) -> QueryResult<ImportSummary> {
+    db_conn.transaction(|| {
+        let mut summary = ImportSummary::default();
+        for mut record in records {
+            let email = non_empty(record.email.take()).map(|email| normalize_email(&email));
+            if let Some(None) = email {
+                summary.skipped.push((record.login, "invalid email".to_string()));
+                continue;
+            }
+            record.email = email.flatten();
+            record.display_name = non_empty(record.display_name);
+            if let Some(reason) = skip_reason(db_conn, &record)? {
+                summary.skipped.push((record.login, reason.to_string()));
+                continue;
+            }
+            let user_id = insert_into(users::dsl::users)
+                .values((
+                    users::dsl::login.eq(Some(&record.login)),
+                    users::dsl::password.eq(Some(&record.password_hash)),
+                    users::dsl::email.eq(&record.email),
+                    users::dsl::display_name.eq(&record.display_name),
+                    users::dsl::guest.eq(false),
+                ))
+                .returning(users::dsl::user_id)
+                .get_result::<i32>(db_conn)?;
+            record_event(db_conn, None, Some(user_id), USER_IMPORTED, None);
+            summary.imported += 1;
+        }
+        Ok(summary)
+    })
+}
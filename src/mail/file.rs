@@ -0,0 +1,44 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use crate::mail::{Email, MailError, Mailer};
+
+/// Development mailer that appends every message to a file instead of sending it.
+pub struct FileMailer {
+    path: Option<String>,
+    lock: Mutex<()>,
+}
+
+impl FileMailer {
+    pub fn new(path: Option<String>) -> FileMailer {
+        FileMailer {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn write(&self, out: &mut dyn Write, email: &Email) -> io::Result<()> {
+        writeln!(out, "To: {}", email.to)?;
+        writeln!(out, "Subject: {}", email.subject)?;
+        writeln!(out)?;
+        writeln!(out, "{}", email.body)?;
+        writeln!(out, "---")?;
+        out.flush()
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> Result<(), MailError> {
+        let _guard = self.lock.lock().unwrap();
+        let result = match &self.path {
+            Some(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| self.write(&mut file, email)),
+            None => self.write(&mut io::stdout(), email),
+        };
+        result.map_err(|e| MailError::DeliveryError(e.to_string()))
+    }
+}
@@ -0,0 +1,47 @@
+use std::env;
+use std::sync::Arc;
+use thiserror::Error;
+
+pub mod file;
+pub mod smtp;
+
+#[derive(Error, Debug)]
+pub enum MailError {
+    #[error("invalid address: {0}")]
+    InvalidAddress(String),
+    #[error("mail delivery failed: {0}")]
+    DeliveryError(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub trait Mailer {
+    fn send(&self, email: &Email) -> Result<(), MailError>;
+}
+
+pub type SharedMailer = Arc<dyn Mailer + Send + Sync>;
+
+/// Delivers on a blocking thread without waiting for it, so a slow mail server holds up neither
+/// the executor nor the response. Failures are only logged, prefixed with `what`.
+pub fn send_in_background(mailer: &SharedMailer, email: Email, what: String) {
+    let mailer = mailer.clone();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = mailer.send(&email) {
+            eprintln!("{} not sent: {}", what, e);
+        }
+    });
+}
+
+/// Picks the mailer from `MAILER`: `smtp` delivers through `SMTP_*` settings,
+/// anything else writes messages to `MAIL_FILE` (or stdout when it is unset).
+pub fn from_env() -> SharedMailer {
+    match env::var("MAILER").as_deref() {
+        Ok("smtp") => Arc::new(smtp::SmtpMailer::from_env()),
+        _ => Arc::new(file::FileMailer::new(env::var("MAIL_FILE").ok())),
+    }
+}
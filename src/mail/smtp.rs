@@ -0,0 +1,50 @@
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use std::env;
+
+use crate::mail::{Email, MailError, Mailer};
+
+pub struct SmtpMailer {
+    from: String,
+    transport: SmtpTransport,
+}
+
+impl SmtpMailer {
+    pub fn from_env() -> SmtpMailer {
+        let host = env::var("SMTP_HOST").expect("SMTP_HOST must be set");
+        let mut builder = SmtpTransport::starttls_relay(&host)
+            .unwrap_or_else(|_| panic!("Invalid SMTP host {}", host));
+        if let Ok(port) = env::var("SMTP_PORT") {
+            builder = builder.port(port.parse().expect("SMTP_PORT must be a number"));
+        }
+        if let (Ok(username), Ok(password)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        SmtpMailer {
+            from: env::var("MAIL_FROM").expect("MAIL_FROM must be set"),
+            transport: builder.build(),
+        }
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: &Email) -> Result<(), MailError> {
+        let message = Message::builder()
+            .from(
+                self.from
+                    .parse()
+                    .map_err(|_| MailError::InvalidAddress(self.from.clone()))?,
+            )
+            .to(email
+                .to
+                .parse()
+                .map_err(|_| MailError::InvalidAddress(email.to.clone()))?)
+            .subject(email.subject.clone())
+            .body(email.body.clone())
+            .map_err(|e| MailError::DeliveryError(e.to_string()))?;
+        self.transport
+            .send(&message)
+            .map(|_| ())
+            .map_err(|e| MailError::DeliveryError(e.to_string()))
+    }
+}
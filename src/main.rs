@@ -1,10 +1,15 @@
+#![recursion_limit = "256"]
+
 #[macro_use]
 extern crate diesel;
 
+use std::env;
 use std::sync::{Arc, Mutex};
 use dotenv::dotenv;
+mod cli;
 mod db;
 mod handlers;
+mod mail;
 mod models;
 mod routes;
 mod security;
@@ -12,10 +17,23 @@ mod security;
 #[tokio::main]
 async fn main() {
     dotenv().expect(".env file not found");
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args));
+    }
+
+    security::keys::install(security::keys::from_env().expect("Error loading JWT signing keys"));
+    tokio::spawn(security::keys::watch());
     let db_connection = db::connection::establish_connection();
     let db = Arc::new(Mutex::new(db_connection));
+    tokio::spawn(handlers::membership_handler::clean_up_memberships(db.clone()));
+
+    let mailer = mail::from_env();
+    let throttle = Arc::new(security::throttle::Throttle::default());
+    let oidc = Arc::new(security::oidc::OidcClient::from_env());
+    let webauthn = Arc::new(security::webauthn::Webauthn::from_env());
 
-    let api = routes::parkings_routes(db);
+    let api = routes::parkings_routes(db, mailer, throttle, oidc, webauthn);
 
     warp::serve(api).run(([127, 0, 0, 1], 8080)).await;
 }
@@ -0,0 +1,13 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Queryable, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub audit_id: i32,
+    pub actor_id: Option<i32>,
+    pub subject_id: Option<i32>,
+    pub action: String,
+    pub details: Option<String>,
+    pub created_at: NaiveDateTime,
+}
@@ -0,0 +1,37 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+pub const PENDING: &str = "pending";
+pub const APPROVED: &str = "approved";
+pub const REJECTED: &str = "rejected";
+
+#[derive(Queryable, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JoinRequest {
+    pub join_request_id: i32,
+    pub parking_id: i32,
+    pub user_id: i32,
+    pub status: String,
+    pub created_at: NaiveDateTime,
+    pub decided_at: Option<NaiveDateTime>,
+    pub decided_by: Option<i32>,
+}
+
+impl JoinRequest {
+    pub fn is_pending(&self) -> bool {
+        self.status == PENDING
+    }
+}
+
+/// A pending request as the parking admin sees it.
+#[derive(Queryable, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingJoinRequest {
+    pub join_request_id: i32,
+    pub user_id: i32,
+    pub login: Option<String>,
+    pub display_name: Option<String>,
+    pub email: Option<String>,
+    pub guest: bool,
+    pub created_at: NaiveDateTime,
+}
@@ -1,3 +1,10 @@
+pub mod api_key;
+pub mod audit_entry;
+pub mod join_request;
+pub mod oidc_identity;
 pub mod parking;
 pub mod parking_consumer;
+pub mod session;
+pub mod totp_credential;
 pub mod user;
+pub mod webauthn_credential;
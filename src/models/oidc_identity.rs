@@ -0,0 +1,11 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Queryable, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub user_id: i32,
+    pub created_at: NaiveDateTime,
+}
@@ -1,15 +1,51 @@
 use diesel::*;
 use serde::{Deserialize, Serialize};
 
+use crate::db::db_schema::parkings;
+
 #[derive(Queryable, PartialEq, Debug, Deserialize, Serialize)]
 pub struct Parking {
     pub parking_id: i32,
     pub name: String,
     pub password: String,
     pub admin_id: i32,
+    pub require_verified_email: bool,
+    pub require_approval: bool,
+    pub max_members: Option<i32>,
+    pub allowed_email_domains: Vec<String>,
+    pub public: bool,
 }
 
 impl Parking {
+    /// True when no domains are configured or the address is on one of them.
+    pub fn allows_email(&self, email: &str) -> bool {
+        if self.allowed_email_domains.is_empty() {
+            return true;
+        }
+        match email.rsplit_once('@') {
+            Some((_, domain)) => self.allowed_email_domains.contains(&domain.to_lowercase()),
+            None => false,
+        }
+    }
+
+    pub fn to_settings(&self) -> ParkingSettings {
+        ParkingSettings {
+            require_verified_email: self.require_verified_email,
+            require_approval: self.require_approval,
+            max_members: self.max_members,
+            allowed_email_domains: self.allowed_email_domains.clone(),
+            public: self.public,
+        }
+    }
+
+    pub fn to_public_parking(&self) -> PublicParking {
+        PublicParking {
+            id: self.parking_id,
+            name: self.name.clone(),
+            require_approval: self.require_approval,
+        }
+    }
+
     pub fn to_parking_without_password(&self) -> ParkingWithoutPassword {
         ParkingWithoutPassword {
             id: self.parking_id,
@@ -26,3 +62,94 @@ pub struct ParkingWithoutPassword {
     pub name: String,
     pub admin_id: i32,
 }
+
+/// What search shows of a public parking.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicParking {
+    pub id: i32,
+    pub name: String,
+    /// Joining with the password skips approval unless this is set.
+    pub require_approval: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Membership {
+    pub parking_id: i32,
+    pub name: String,
+    pub role: String,
+}
+
+/// Join rules the admin of a parking controls.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ParkingSettings {
+    pub require_verified_email: bool,
+    /// Joining creates a join request the admin has to approve.
+    pub require_approval: bool,
+    /// Consumers the parking takes at most; unlimited when unset.
+    pub max_members: Option<i32>,
+    /// Only users with a verified address on one of these domains may join; anyone when empty.
+    pub allowed_email_domains: Vec<String>,
+    /// Public parkings show up in search and can be asked to join without the password.
+    pub public: bool,
+}
+
+/// Fields omitted from the request are left untouched; a `maxMembers` of 0 removes the limit
+/// and an empty `allowedEmailDomains` list lifts the domain restriction.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateParkingSettingsRequest {
+    pub require_verified_email: Option<bool>,
+    pub require_approval: Option<bool>,
+    pub max_members: Option<i32>,
+    pub allowed_email_domains: Option<Vec<String>>,
+    pub public: Option<bool>,
+}
+
+#[derive(AsChangeset, Debug, Default)]
+#[table_name = "parkings"]
+pub struct ParkingSettingsChanges {
+    pub require_verified_email: Option<bool>,
+    pub require_approval: Option<bool>,
+    pub max_members: Option<Option<i32>>,
+    pub allowed_email_domains: Option<Vec<String>>,
+    pub public: Option<bool>,
+}
+
+/// Lower case, without a leading `@`, blanks and duplicates.
+fn normalize_domains(domains: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = domains
+        .iter()
+        .map(|domain| domain.trim().trim_start_matches('@').to_lowercase())
+        .filter(|domain| !domain.is_empty())
+        .collect();
+    normalized.sort();
+    normalized.dedup();
+    normalized
+}
+
+impl UpdateParkingSettingsRequest {
+    pub fn into_changes(self) -> ParkingSettingsChanges {
+        ParkingSettingsChanges {
+            require_verified_email: self.require_verified_email,
+            require_approval: self.require_approval,
+            max_members: self
+                .max_members
+                .map(|max| if max > 0 { Some(max) } else { None }),
+            allowed_email_domains: self.allowed_email_domains.map(normalize_domains),
+            public: self.public,
+        }
+    }
+}
+
+impl ParkingSettingsChanges {
+    pub fn is_empty(&self) -> bool {
+        self.require_verified_email.is_none()
+            && self.require_approval.is_none()
+            && self.max_members.is_none()
+            && self.allowed_email_domains.is_none()
+            && self.public.is_none()
+    }
+}
@@ -1,5 +1,11 @@
-#[derive(Queryable, PartialEq, Debug)]
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Queryable, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ParkingConsumer {
     pub parking_id: i32,
     pub consumer_id: i32,
+    /// After this the membership is hidden, and the cleanup task deletes it.
+    pub expires_at: Option<NaiveDateTime>,
 }
@@ -0,0 +1,90 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+#[derive(Queryable, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub session_id: i32,
+    pub user_id: i32,
+    pub created_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub device_name: Option<String>,
+    pub ip_address: Option<String>,
+    pub last_seen_at: NaiveDateTime,
+    pub impersonator_id: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    pub session_id: i32,
+    pub device_name: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+    /// Set on the session the listing request was made with.
+    pub current: bool,
+    /// The system admin who opened this session to see the account as its owner does.
+    pub impersonated_by: Option<i32>,
+}
+
+impl Session {
+    pub fn to_session_info(&self, current_session_id: i32) -> SessionInfo {
+        SessionInfo {
+            session_id: self.session_id,
+            device_name: self.device_name.clone(),
+            ip_address: self.ip_address.clone(),
+            created_at: self.created_at,
+            last_seen_at: self.last_seen_at,
+            current: self.session_id == current_session_id,
+            impersonated_by: self.impersonator_id,
+        }
+    }
+}
+
+/// What the request that opens a session tells about the client.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+const BROWSERS: [(&str, &str); 6] = [
+    ("Edg/", "Edge"),
+    ("OPR/", "Opera"),
+    ("Firefox/", "Firefox"),
+    ("Chrome/", "Chrome"),
+    ("Safari/", "Safari"),
+    ("okhttp/", "Android app"),
+];
+
+const PLATFORMS: [(&str, &str); 6] = [
+    ("iPhone", "iPhone"),
+    ("iPad", "iPad"),
+    ("Android", "Android"),
+    ("Windows", "Windows"),
+    ("Mac OS X", "macOS"),
+    ("Linux", "Linux"),
+];
+
+impl ClientInfo {
+    /// A short human readable label such as "Firefox on Windows", derived from the User-Agent.
+    pub fn device_name(&self) -> Option<String> {
+        let user_agent = self.user_agent.as_deref()?.trim();
+        if user_agent.is_empty() {
+            return None;
+        }
+        let find = |table: &[(&str, &'static str)]| {
+            table
+                .iter()
+                .find(|(marker, _)| user_agent.contains(marker))
+                .map(|(_, name)| *name)
+        };
+        match (find(&BROWSERS), find(&PLATFORMS)) {
+            (Some(browser), Some(platform)) => Some(format!("{} on {}", browser, platform)),
+            (Some(name), None) | (None, Some(name)) => Some(name.to_string()),
+            (None, None) => Some(user_agent.chars().take(64).collect()),
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use chrono::NaiveDateTime;
+
+#[derive(Queryable, PartialEq, Debug)]
+pub struct TotpCredential {
+    pub user_id: i32,
+    pub secret: String,
+    pub confirmed_at: Option<NaiveDateTime>,
+    pub last_used_step: i64,
+    pub created_at: NaiveDateTime,
+}
+
+impl TotpCredential {
+    /// Enrollment only takes effect once the user has proven the authenticator works.
+    pub fn is_confirmed(&self) -> bool {
+        self.confirmed_at.is_some()
+    }
+}
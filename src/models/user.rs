@@ -1,10 +1,73 @@
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 
+use crate::db::db_schema::users;
+use crate::models::parking::ParkingWithoutPassword;
+
 #[derive(Queryable, PartialEq, Debug)]
 pub struct User {
     pub id: i32,
     pub login: Option<String>,
     pub password: Option<String>,
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+    pub locale: Option<String>,
+    pub time_zone: Option<String>,
+    pub guest: bool,
+    pub email_verified: bool,
+    pub system_admin: bool,
+    pub disabled_at: Option<NaiveDateTime>,
+}
+
+impl User {
+    /// Guests are created by `join_parking` and have no credentials besides their token.
+    pub fn is_guest(&self) -> bool {
+        self.guest
+    }
+
+    pub fn has_local_credentials(&self) -> bool {
+        self.login.is_some() && self.password.is_some()
+    }
+
+    pub fn has_verified_email(&self) -> bool {
+        self.email.is_some() && self.email_verified
+    }
+
+    /// Operators who may use the `/admin` routes; granted with `user-service users grant-admin`.
+    pub fn is_system_admin(&self) -> bool {
+        self.system_admin
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.disabled_at.is_some()
+    }
+
+    pub fn to_summary(&self) -> UserSummary {
+        UserSummary {
+            id: self.id,
+            login: self.login.clone(),
+            display_name: self.display_name.clone(),
+            email: self.email.clone(),
+            email_verified: self.email_verified,
+            guest: self.guest,
+            system_admin: self.system_admin,
+            disabled_at: self.disabled_at,
+        }
+    }
+}
+
+/// What operators see of an account in the admin API.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSummary {
+    pub id: i32,
+    pub login: Option<String>,
+    pub display_name: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub guest: bool,
+    pub system_admin: bool,
+    pub disabled_at: Option<NaiveDateTime>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -12,4 +75,118 @@ pub struct User {
 pub struct UserCredentials {
     pub login: String,
     pub password: String,
+    /// Browser clients ask for the session in a cookie instead of the response body.
+    #[serde(default)]
+    pub cookie: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RegisterRequest {
+    pub login: String,
+    pub password: String,
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub id: i32,
+    pub login: Option<String>,
+    pub display_name: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub locale: Option<String>,
+    pub time_zone: Option<String>,
+    pub guest: bool,
+    pub mfa_enabled: bool,
+    pub administered_parkings: Vec<ParkingWithoutPassword>,
+    pub consumed_parkings: Vec<ParkingWithoutPassword>,
+}
+
+/// Fields omitted from the request are left untouched; an empty string clears the field.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateProfileRequest {
+    pub display_name: Option<String>,
+    pub email: Option<String>,
+    pub locale: Option<String>,
+    pub time_zone: Option<String>,
+}
+
+#[derive(AsChangeset, Debug, Default)]
+#[table_name = "users"]
+pub struct ProfileChanges {
+    pub display_name: Option<Option<String>>,
+    pub email: Option<Option<String>>,
+    pub email_verified: Option<bool>,
+    pub locale: Option<Option<String>>,
+    pub time_zone: Option<Option<String>>,
+}
+
+fn to_change(value: Option<String>) -> Option<Option<String>> {
+    value.map(|v| {
+        let v = v.trim().to_string();
+        if v.is_empty() {
+            None
+        } else {
+            Some(v)
+        }
+    })
+}
+
+/// Trims and lowercases an address, or returns `None` when it is not one. Addresses are stored
+/// and looked up in this form, so they compare case-insensitively.
+pub fn normalize_email(email: &str) -> Option<String> {
+    let email = email.trim().to_lowercase();
+    let (local, domain) = email.split_once('@')?;
+    let valid = !local.is_empty()
+        && !domain.contains('@')
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !email.chars().any(char::is_whitespace);
+    if valid {
+        Some(email)
+    } else {
+        None
+    }
+}
+
+/// Language tags in the BCP 47 shape, such as `pl`, `en-GB` or `zh-Hant-TW`.
+pub fn is_valid_locale(locale: &str) -> bool {
+    let mut subtags = locale.split('-');
+    let language = subtags.next().unwrap_or_default();
+    locale.len() <= 35
+        && (2..=8).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|tag| (1..=8).contains(&tag.len()) && tag.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// IANA time zone names such as `Europe/Warsaw`.
+pub fn is_valid_time_zone(time_zone: &str) -> bool {
+    time_zone.parse::<chrono_tz::Tz>().is_ok()
+}
+
+impl UpdateProfileRequest {
+    pub fn into_changes(self) -> ProfileChanges {
+        ProfileChanges {
+            display_name: to_change(self.display_name),
+            email: to_change(self.email),
+            email_verified: None,
+            locale: to_change(self.locale),
+            time_zone: to_change(self.time_zone),
+        }
+    }
+}
+
+impl ProfileChanges {
+    pub fn is_empty(&self) -> bool {
+        self.display_name.is_none()
+            && self.email.is_none()
+            && self.email_verified.is_none()
+            && self.locale.is_none()
+            && self.time_zone.is_none()
+    }
 }
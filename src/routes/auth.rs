@@ -1,29 +1,51 @@
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
-use warp::http::HeaderMap;
+use std::env;
+use std::ops::Deref;
+use ring::constant_time::verify_slices_are_equal;
+use warp::http::{HeaderMap, Method};
 use warp::hyper::header::AUTHORIZATION;
 use warp::hyper::http::HeaderValue;
+use warp::path::FullPath;
 use warp::{reject, Rejection};
-use std::env;
+use diesel::*;
+use crate::db::db_schema::users;
+use crate::handlers::api_key_handler::authenticate_api_key;
+use crate::handlers::audit_handler::{record_event, IMPERSONATED_REQUEST};
 use crate::handlers::error_handler::Error;
+use crate::handlers::session_handler::{ensure_enabled, validate_token};
+use crate::models::user::User;
+use crate::routes::Db;
+use crate::security::cookie::{cookie_value, csrf_token_matches, SESSION_COOKIE};
 use crate::security::Claims;
 
 const BEARER: &str = "Bearer ";
+const BASIC: &str = "Basic ";
 
+/// Impersonation tokens get every request audited, refused ones included, and stay within
+/// safe methods when they are read-only.
 pub async fn authorize(
-    (headers, obligatory): (HeaderMap<HeaderValue>, bool),
-) -> Result<Option<i32>, Rejection> {
-    let jwt_secret = env::var("JWT_SECRET").unwrap();
-    match jwt_from_header(&headers) {
+    (headers, method, path, obligatory, db): (HeaderMap<HeaderValue>, Method, FullPath, bool, Db),
+) -> Result<Option<Claims>, Rejection> {
+    match session_token(&headers, &method) {
         Ok(jwt) => {
-            let decoded = decode::<Claims>(
-                &jwt,
-                &DecodingKey::from_secret(jwt_secret.as_bytes()),
-                &Validation::new(Algorithm::HS512),
-            )
-                .map_err(|_| reject::custom(Error::JWTTokenError))?;
-
-            Ok(Some(decoded.claims.id))
+            let db_conn_mutex = db.lock().unwrap();
+            let claims = validate_token(db_conn_mutex.deref(), &jwt).map_err(reject::custom)?;
+            ensure_enabled(db_conn_mutex.deref(), claims.id).map_err(reject::custom)?;
+            if let Some(actor_id) = claims.actor_id {
+                record_event(
+                    db_conn_mutex.deref(),
+                    Some(actor_id),
+                    Some(claims.id),
+                    IMPERSONATED_REQUEST,
+                    Some(format!("{} {}", method, path.as_str())),
+                );
+                if claims.read_only && !method.is_safe() {
+                    return Err(reject::custom(Error::ReadOnlyTokenError));
+                }
+            }
+            Ok(Some(claims))
         }
+        // a forged cross-site request must not fall back to acting anonymously
+        Err(Error::CsrfError) => Err(reject::custom(Error::CsrfError)),
         Err(e) => {
             if obligatory {
                 Err(reject::custom(e))
@@ -34,6 +56,78 @@ pub async fn authorize(
     }
 }
 
+/// Returns the id of the signed-in user when they are a system admin. Impersonation tokens never
+/// pass, even when issued by an admin.
+pub async fn authorize_system_admin(claims: Option<Claims>, db: Db) -> Result<i32, Rejection> {
+    let claims = claims
+        .filter(|claims| claims.actor_id.is_none())
+        .ok_or_else(|| reject::custom(Error::NoPermissionError))?;
+    let db_conn_mutex = db.lock().unwrap();
+    let user = users::dsl::users.find(claims.id).first::<User>(db_conn_mutex.deref());
+    if user.map(|user| user.is_system_admin()).unwrap_or(false) {
+        Ok(claims.id)
+    } else {
+        Err(reject::custom(Error::NoPermissionError))
+    }
+}
+
+/// Lets only the account holder through: an impersonating admin must not be able to add a
+/// password, passkey, second factor, new email address or any other way back into the account.
+pub async fn refuse_impersonation(claims: Option<Claims>) -> Result<Option<Claims>, Rejection> {
+    match claims {
+        Some(claims) if claims.actor_id.is_some() => Err(reject::custom(Error::ImpersonationError)),
+        claims => Ok(claims),
+    }
+}
+
+/// Checks HTTP Basic credentials against `SERVICE_CLIENTS` (`id:secret` pairs separated by commas)
+/// and returns the client id.
+pub async fn authorize_service(headers: HeaderMap<HeaderValue>) -> Result<String, Rejection> {
+    let (client_id, client_secret) =
+        basic_credentials(&headers).ok_or_else(|| reject::custom(Error::InvalidClientError))?;
+    let clients = env::var("SERVICE_CLIENTS").unwrap_or_default();
+    let known = clients.split(',').filter_map(|c| c.trim().split_once(':')).any(|(id, secret)| {
+        id == client_id && verify_slices_are_equal(secret.as_bytes(), client_secret.as_bytes()).is_ok()
+    });
+    if known {
+        Ok(client_id)
+    } else {
+        Err(reject::custom(Error::InvalidClientError))
+    }
+}
+
+/// Returns the name of the API key when it is valid and carries `scope`.
+pub async fn authorize_api_key(key: String, db: Db, scope: &'static str) -> Result<String, Rejection> {
+    let db_conn_mutex = db.lock().unwrap();
+    authenticate_api_key(db_conn_mutex.deref(), &key, scope)
+        .map(|api_key| api_key.name)
+        .map_err(reject::custom)
+}
+
+fn basic_credentials(headers: &HeaderMap<HeaderValue>) -> Option<(String, String)> {
+    let header = std::str::from_utf8(headers.get(AUTHORIZATION)?.as_bytes()).ok()?;
+    let encoded = header.strip_prefix(BASIC)?;
+    let decoded = String::from_utf8(base64::decode(encoded.trim()).ok()?).ok()?;
+    let (id, secret) = decoded.split_once(':')?;
+    Some((id.to_string(), secret.to_string()))
+}
+
+/// The bearer token, or else the session cookie of a browser client. Browsers attach cookies to
+/// cross-site requests as well, so cookie-authenticated requests that change state must also
+/// carry the CSRF token.
+fn session_token(headers: &HeaderMap<HeaderValue>, method: &Method) -> Result<String, Error> {
+    if headers.contains_key(AUTHORIZATION) {
+        return jwt_from_header(headers);
+    }
+    let token = cookie_value(headers, SESSION_COOKIE)
+        .filter(|token| !token.is_empty())
+        .ok_or(Error::NoAuthHeaderError)?;
+    if !method.is_safe() && !csrf_token_matches(headers) {
+        return Err(Error::CsrfError);
+    }
+    Ok(token)
+}
+
 fn jwt_from_header(headers: &HeaderMap<HeaderValue>) -> Result<String, Error> {
     let header = match headers.get(AUTHORIZATION) {
         Some(h) => h,
@@ -1,27 +1,131 @@
+use crate::mail::SharedMailer;
+use crate::models::session::ClientInfo;
 use crate::routes::{Db, auth};
+use crate::security::oidc::SharedOidc;
+use crate::security::throttle::SharedThrottle;
+use crate::security::webauthn::SharedWebauthn;
+use crate::security::Claims;
 use serde::de::DeserializeOwned;
 use std::convert::Infallible;
 use std::env;
-use warp::http::{HeaderMap, HeaderValue};
+use std::net::{IpAddr, SocketAddr};
+use warp::http::{HeaderMap, HeaderValue, Method};
+use warp::path::FullPath;
 use warp::{filters, Filter, Rejection};
 
 pub fn with_db(db: Db) -> impl Filter<Extract = (Db,), Error = Infallible> + Clone {
     warp::any().map(move || db.clone())
 }
 
+pub fn with_mailer(
+    mailer: SharedMailer,
+) -> impl Filter<Extract = (SharedMailer,), Error = Infallible> + Clone {
+    warp::any().map(move || mailer.clone())
+}
+
+pub fn with_throttle(
+    throttle: SharedThrottle,
+) -> impl Filter<Extract = (SharedThrottle,), Error = Infallible> + Clone {
+    warp::any().map(move || throttle.clone())
+}
+
+pub fn with_oidc(oidc: SharedOidc) -> impl Filter<Extract = (SharedOidc,), Error = Infallible> + Clone {
+    warp::any().map(move || oidc.clone())
+}
+
+pub fn with_webauthn(
+    webauthn: SharedWebauthn,
+) -> impl Filter<Extract = (SharedWebauthn,), Error = Infallible> + Clone {
+    warp::any().map(move || webauthn.clone())
+}
+
+/// Caller address, taken from the last `X-Forwarded-For` entry when `TRUST_FORWARDED_FOR=true`.
+/// That entry is the one appended by the trusted proxy; earlier ones are set by the client.
+pub fn client_ip() -> impl Filter<Extract = (Option<IpAddr>,), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .map(|remote: Option<SocketAddr>, forwarded: Option<String>| {
+            let trust_forwarded = env::var("TRUST_FORWARDED_FOR")
+                .map(|v| v == "true")
+                .unwrap_or(false);
+            forwarded
+                .filter(|_| trust_forwarded)
+                .and_then(|f| f.rsplit(',').next().and_then(|ip| ip.trim().parse().ok()))
+                .or_else(|| remote.map(|addr| addr.ip()))
+        })
+}
+
+pub fn client_info() -> impl Filter<Extract = (ClientInfo,), Error = Rejection> + Clone {
+    client_ip()
+        .and(warp::header::optional::<String>("user-agent"))
+        .map(|ip: Option<IpAddr>, user_agent: Option<String>| ClientInfo { ip, user_agent })
+}
+
 pub fn json_body<T: DeserializeOwned + Send>(
 ) -> impl Filter<Extract = (T,), Error = Rejection> + Clone {
     warp::body::content_length_limit(1024 * 32).and(warp::body::json())
 }
 
-pub fn with_jwt_secret() -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
-    warp::any().map(move || env::var("JWT_SECRET").unwrap())
+pub fn form_body<T: DeserializeOwned + Send>(
+) -> impl Filter<Extract = (T,), Error = Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 32).and(warp::body::form())
+}
+
+pub fn with_service_client() -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    filters::header::headers_cloned().and_then(auth::authorize_service)
 }
 
-pub fn with_auth(
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+pub fn with_api_key(
+    db: Db,
+    scope: &'static str,
+) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::header::<String>(API_KEY_HEADER)
+        .and(with_db(db))
+        .and_then(move |key: String, db: Db| auth::authorize_api_key(key, db, scope))
+}
+
+pub fn with_claims(
+    db: Db,
     obligatory: bool,
-) -> impl Filter<Extract = (Option<i32>,), Error = Rejection> + Clone {
+) -> impl Filter<Extract = (Option<Claims>,), Error = Rejection> + Clone {
     filters::header::headers_cloned()
-        .map(move |headers: HeaderMap<HeaderValue>| (headers, obligatory))
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(with_db(db))
+        .map(move |headers: HeaderMap<HeaderValue>, method: Method, path: FullPath, db: Db| {
+            (headers, method, path, obligatory, db)
+        })
         .and_then(auth::authorize)
 }
+
+/// Signed-in system admins only; extracts their user id.
+pub fn with_system_admin(db: Db) -> impl Filter<Extract = (i32,), Error = Rejection> + Clone {
+    with_claims(db.clone(), true)
+        .and(with_db(db))
+        .and_then(auth::authorize_system_admin)
+}
+
+pub fn with_auth(
+    db: Db,
+    obligatory: bool,
+) -> impl Filter<Extract = (Option<i32>,), Error = Rejection> + Clone {
+    with_claims(db, obligatory).map(|claims: Option<Claims>| claims.map(|c| c.id))
+}
+
+/// Like `with_claims`, but refuses impersonation tokens; for routes that add credentials.
+pub fn with_own_claims(
+    db: Db,
+    obligatory: bool,
+) -> impl Filter<Extract = (Option<Claims>,), Error = Rejection> + Clone {
+    with_claims(db, obligatory).and_then(auth::refuse_impersonation)
+}
+
+/// Like `with_auth`, but refuses impersonation tokens; for routes that add credentials.
+pub fn with_own_auth(
+    db: Db,
+    obligatory: bool,
+) -> impl Filter<Extract = (Option<i32>,), Error = Rejection> + Clone {
+    with_own_claims(db, obligatory).map(|claims: Option<Claims>| claims.map(|c| c.id))
+}
@@ -4,9 +4,40 @@ use std::sync::{Arc, Mutex};
 use diesel::PgConnection;
 use warp::{Filter, Rejection, Reply};
 
-use crate::handlers::{error_handler, parking_handler, parking_password_handler, user_handler};
-use crate::handlers::parking_handler::{CreateParkingRequest, JoinParkingRequest};
-use crate::models::user::UserCredentials;
+use crate::handlers::{
+    email_verification_handler, error_handler, guest_handler, introspection_handler,
+    join_request_handler, magic_link_handler, membership_handler, mfa_handler, oidc_handler,
+    parking_handler, parking_password_handler, parking_settings_handler, passkey_handler,
+    password_reset_handler, profile_handler, session_handler, system_admin_handler, user_handler,
+};
+use crate::handlers::api_key_handler::{SCOPE_INTROSPECT, SCOPE_MEMBERSHIPS_READ};
+use crate::handlers::email_verification_handler::VerifyEmailRequest;
+use crate::handlers::guest_handler::{PairDeviceRequest, RecoverGuestRequest};
+use crate::handlers::introspection_handler::IntrospectionRequest;
+use crate::handlers::magic_link_handler::{MagicLinkExchangeRequest, MagicLinkRequest};
+use crate::handlers::membership_handler::MembershipExpiryRequest;
+use crate::handlers::mfa_handler::{MfaCodeRequest, MfaLoginRequest};
+use crate::handlers::oidc_handler::OidcCallbackRequest;
+use crate::handlers::parking_handler::{
+    CreateParkingRequest, JoinParkingRequest, SearchParkingsQuery, TransferParkingRequest,
+};
+use crate::handlers::passkey_handler::{
+    PasskeyLoginOptionsRequest, PasskeyLoginRequest, RegisterPasskeyRequest,
+};
+use crate::handlers::profile_handler::DeleteAccountQuery;
+use crate::handlers::system_admin_handler::{
+    ImpersonationRequest, ReassignParkingRequest, UserSearchQuery,
+};
+use crate::handlers::user_handler::ChangePasswordRequest;
+use crate::handlers::password_reset_handler::{PasswordResetRequest, ResetPasswordRequest};
+use crate::mail::SharedMailer;
+use crate::models::parking::UpdateParkingSettingsRequest;
+use crate::models::user::{RegisterRequest, UpdateProfileRequest, UserCredentials};
+use crate::security;
+use crate::security::cookie::OIDC_STATE_COOKIE;
+use crate::security::oidc::SharedOidc;
+use crate::security::throttle::SharedThrottle;
+use crate::security::webauthn::SharedWebauthn;
 
 mod filters;
 mod auth;
@@ -15,67 +46,702 @@ pub type Db = Arc<Mutex<PgConnection>>;
 
 pub fn parkings_routes(
     db_connection: Db,
+    mailer: SharedMailer,
+    throttle: SharedThrottle,
+    oidc: SharedOidc,
+    webauthn: SharedWebauthn,
 ) -> impl Filter<Extract = impl Reply, Error = Infallible> + Clone {
-    register(db_connection.clone())
+    register(db_connection.clone(), mailer.clone())
         .or(parking_create(db_connection.clone()))
-        .or(log_in(db_connection.clone()))
+        .or(log_in(db_connection.clone(), throttle.clone()))
+        .or(complete_mfa_login(db_connection.clone(), throttle.clone()))
+        .or(request_magic_link(db_connection.clone(), mailer.clone(), throttle.clone()))
+        .or(exchange_magic_link(db_connection.clone()))
+        .or(oidc_login(db_connection.clone(), oidc.clone()))
+        .or(oidc_callback(db_connection.clone(), oidc))
+        .or(passkey_login_options(db_connection.clone(), webauthn.clone()))
+        .or(passkey_login(db_connection.clone(), webauthn.clone()))
+        .or(passkey_registration_options(db_connection.clone(), webauthn.clone()))
+        .or(register_passkey(db_connection.clone(), webauthn))
+        .or(list_passkeys(db_connection.clone()))
+        .or(delete_passkey(db_connection.clone()))
         .or(list_parkings(db_connection.clone()))
-        .or(parking_join(db_connection.clone()))
+        .or(search_parkings(db_connection.clone()))
+        .or(parking_join(db_connection.clone(), throttle.clone(), mailer.clone()))
+        .or(list_members(db_connection.clone()))
+        .or(set_membership_expiry(db_connection.clone()))
+        .or(list_join_requests(db_connection.clone()))
+        .or(list_own_join_requests(db_connection.clone()))
+        .or(approve_join_request(db_connection.clone(), mailer.clone()))
+        .or(reject_join_request(db_connection.clone(), mailer.clone()))
+        .or(recover_guest(db_connection.clone(), throttle.clone()))
+        .or(rotate_recovery_code(db_connection.clone()))
+        .or(create_pairing_code(db_connection.clone()))
+        .or(pair_device(db_connection.clone(), throttle.clone()))
         .or(get_parking_password(db_connection.clone()))
+        .or(jwks())
+        .or(introspect(db_connection.clone()))
+        .or(user_memberships(db_connection.clone()))
+        .or(parking_transfer(db_connection.clone()))
+        .or(get_parking_settings(db_connection.clone()))
+        .or(update_parking_settings(db_connection.clone()))
+        .or(admin_search_users(db_connection.clone()))
+        .or(admin_get_user(db_connection.clone()))
+        .or(admin_disable_user(db_connection.clone()))
+        .or(admin_enable_user(db_connection.clone()))
+        .or(admin_force_password_reset(db_connection.clone(), mailer.clone()))
+        .or(admin_impersonate_user(db_connection.clone()))
+        .or(admin_reassign_parking(db_connection.clone()))
+        .or(get_profile(db_connection.clone()))
+        .or(update_profile(db_connection.clone(), mailer.clone()))
+        .or(request_email_verification(db_connection.clone(), mailer.clone()))
+        .or(verify_email(db_connection.clone()))
+        .or(delete_account(db_connection.clone()))
+        .or(export_account(db_connection.clone()))
+        .or(change_password(db_connection.clone()))
+        .or(list_sessions(db_connection.clone()))
+        .or(revoke_session(db_connection.clone()))
+        .or(enroll_totp(db_connection.clone()))
+        .or(confirm_totp(db_connection.clone()))
+        .or(disable_totp(db_connection.clone()))
+        .or(regenerate_recovery_codes(db_connection.clone()))
+        .or(request_password_reset(db_connection.clone(), mailer, throttle))
+        .or(reset_password(db_connection.clone()))
         .recover(error_handler::handle_rejection)
 }
 
+pub fn jwks() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!(".well-known" / "jwks.json")
+        .and(warp::get())
+        .map(|| warp::reply::json(&security::keys::jwks()))
+}
+
+pub fn introspect(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("introspect")
+        .and(warp::post())
+        .and(filters::form_body::<IntrospectionRequest>())
+        .and(filters::with_db(db.clone()))
+        .and(
+            filters::with_service_client()
+                .or(filters::with_api_key(db, SCOPE_INTROSPECT))
+                .unify(),
+        )
+        .and_then(introspection_handler::introspect)
+}
+
+pub fn user_memberships(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("internal" / "users" / i32 / "memberships")
+        .and(warp::get())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_api_key(db, SCOPE_MEMBERSHIPS_READ))
+        .and_then(parking_handler::list_user_memberships)
+}
+
 pub fn get_parking_password(
     db: Db,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     warp::path!("parkings" / i32 / "password")
         .and(warp::get())
-        .and(filters::with_db(db))
-        .and(filters::with_auth(true))
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, true))
         .and_then(parking_password_handler::get_parking_password)
 }
 
+pub fn parking_transfer(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("parkings" / i32 / "admin")
+        .and(warp::put())
+        .and(filters::json_body::<TransferParkingRequest>())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, true))
+        .and_then(parking_handler::transfer_parking)
+}
+
+pub fn get_parking_settings(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("parkings" / i32 / "settings")
+        .and(warp::get())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, true))
+        .and_then(parking_settings_handler::get_settings)
+}
+
+pub fn update_parking_settings(
+    db: Db,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("parkings" / i32)
+        .and(warp::patch())
+        .and(filters::json_body::<UpdateParkingSettingsRequest>())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, true))
+        .and_then(parking_settings_handler::update_settings)
+}
+
 pub fn parking_create(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     warp::path!("parkings")
         .and(warp::post())
         .and(filters::json_body::<CreateParkingRequest>())
-        .and(filters::with_db(db))
-        .and(filters::with_auth(true))
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, true))
         .and_then(parking_handler::create_parking)
 }
 
-pub fn parking_join(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+pub fn parking_join(
+    db: Db,
+    throttle: SharedThrottle,
+    mailer: SharedMailer,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     warp::path!("join_parking")
         .and(warp::post())
         .and(filters::json_body::<JoinParkingRequest>())
-        .and(filters::with_db(db))
-        .and(filters::with_auth(false))
-        .and(filters::with_jwt_secret())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, false))
+        .and(filters::with_throttle(throttle))
+        .and(filters::with_mailer(mailer))
+        .and(filters::client_info())
         .and_then(parking_handler::join_parking)
 }
 
+pub fn list_members(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("parkings" / i32 / "members")
+        .and(warp::get())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, true))
+        .and_then(membership_handler::list_members)
+}
+
+pub fn set_membership_expiry(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("parkings" / i32 / "members" / i32)
+        .and(warp::patch())
+        .and(filters::json_body::<MembershipExpiryRequest>())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, true))
+        .and_then(membership_handler::set_membership_expiry)
+}
+
+pub fn list_join_requests(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("parkings" / i32 / "join-requests")
+        .and(warp::get())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, true))
+        .and_then(join_request_handler::list_join_requests)
+}
+
+pub fn list_own_join_requests(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "join-requests")
+        .and(warp::get())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, true))
+        .and_then(join_request_handler::list_own_join_requests)
+}
+
+pub fn approve_join_request(
+    db: Db,
+    mailer: SharedMailer,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("parkings" / i32 / "join-requests" / i32 / "approve")
+        .and(warp::post())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_mailer(mailer))
+        .and(filters::with_auth(db, true))
+        .and_then(join_request_handler::approve_join_request)
+}
+
+pub fn reject_join_request(
+    db: Db,
+    mailer: SharedMailer,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("parkings" / i32 / "join-requests" / i32 / "reject")
+        .and(warp::post())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_mailer(mailer))
+        .and(filters::with_auth(db, true))
+        .and_then(join_request_handler::reject_join_request)
+}
+
+pub fn recover_guest(
+    db: Db,
+    throttle: SharedThrottle,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("guest" / "recover")
+        .and(warp::post())
+        .and(filters::json_body::<RecoverGuestRequest>())
+        .and(filters::with_db(db))
+        .and(filters::with_throttle(throttle))
+        .and(filters::client_info())
+        .and_then(guest_handler::recover_guest)
+}
+
+pub fn rotate_recovery_code(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "recovery-code")
+        .and(warp::post())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_own_auth(db, true))
+        .and_then(guest_handler::rotate_recovery_code)
+}
+
+pub fn create_pairing_code(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "pairing-code")
+        .and(warp::post())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_own_auth(db, true))
+        .and_then(guest_handler::create_pairing_code)
+}
+
+pub fn pair_device(
+    db: Db,
+    throttle: SharedThrottle,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("pairing")
+        .and(warp::post())
+        .and(filters::json_body::<PairDeviceRequest>())
+        .and(filters::with_db(db))
+        .and(filters::with_throttle(throttle))
+        .and(filters::client_info())
+        .and_then(guest_handler::pair_device)
+}
+
+pub fn search_parkings(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("parkings" / "search")
+        .and(warp::get())
+        .and(warp::query::<SearchParkingsQuery>())
+        .and(filters::with_db(db))
+        .and_then(parking_handler::search_parkings)
+}
+
 pub fn list_parkings(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     warp::path!("parkings")
         .and(warp::get())
-        .and(filters::with_db(db))
-        .and(filters::with_auth(true))
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, true))
         .and_then(parking_handler::list_parkings)
 }
 
-pub fn register(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+pub fn register(
+    db: Db,
+    mailer: SharedMailer,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     warp::path!("register")
         .and(warp::post())
-        .and(filters::json_body::<UserCredentials>())
-        .and(filters::with_db(db))
-        .and(filters::with_auth(false))
+        .and(filters::json_body::<RegisterRequest>())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_mailer(mailer))
+        .and(filters::with_own_auth(db, false))
         .and_then(user_handler::register)
 }
 
-pub fn log_in(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+pub fn log_in(
+    db: Db,
+    throttle: SharedThrottle,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     warp::path!("login")
         .and(warp::post())
         .and(filters::json_body::<UserCredentials>())
         .and(filters::with_db(db))
-        .and(filters::with_jwt_secret())
+        .and(filters::with_throttle(throttle))
+        .and(filters::client_info())
         .and_then(user_handler::log_in)
 }
+
+pub fn complete_mfa_login(
+    db: Db,
+    throttle: SharedThrottle,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("login" / "mfa")
+        .and(warp::post())
+        .and(filters::json_body::<MfaLoginRequest>())
+        .and(filters::with_db(db))
+        .and(filters::with_throttle(throttle))
+        .and(filters::client_info())
+        .and_then(mfa_handler::complete_login)
+}
+
+pub fn request_magic_link(
+    db: Db,
+    mailer: SharedMailer,
+    throttle: SharedThrottle,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("login" / "magic-link")
+        .and(warp::post())
+        .and(filters::json_body::<MagicLinkRequest>())
+        .and(filters::with_db(db))
+        .and(filters::with_mailer(mailer))
+        .and(filters::with_throttle(throttle))
+        .and(filters::client_info())
+        .and_then(magic_link_handler::request_magic_link)
+}
+
+pub fn exchange_magic_link(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("login" / "magic-link" / "exchange")
+        .and(warp::post())
+        .and(filters::json_body::<MagicLinkExchangeRequest>())
+        .and(filters::with_db(db))
+        .and(filters::client_info())
+        .and_then(magic_link_handler::exchange_magic_link)
+}
+
+pub fn oidc_login(
+    db: Db,
+    oidc: SharedOidc,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("login" / "oidc")
+        .and(warp::post())
+        .and(filters::with_oidc(oidc))
+        .and(filters::with_own_auth(db, false))
+        .and_then(oidc_handler::start_login)
+}
+
+pub fn oidc_callback(
+    db: Db,
+    oidc: SharedOidc,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("login" / "oidc" / "callback")
+        .and(warp::post())
+        .and(filters::json_body::<OidcCallbackRequest>())
+        .and(warp::cookie::optional::<String>(OIDC_STATE_COOKIE))
+        .and(filters::with_db(db))
+        .and(filters::with_oidc(oidc))
+        .and(filters::client_info())
+        .and_then(oidc_handler::finish_login)
+}
+
+pub fn passkey_login_options(
+    db: Db,
+    webauthn: SharedWebauthn,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("login" / "passkey" / "options")
+        .and(warp::post())
+        .and(filters::json_body::<PasskeyLoginOptionsRequest>())
+        .and(filters::with_db(db))
+        .and(filters::with_webauthn(webauthn))
+        .and_then(passkey_handler::login_options)
+}
+
+pub fn passkey_login(
+    db: Db,
+    webauthn: SharedWebauthn,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("login" / "passkey")
+        .and(warp::post())
+        .and(filters::json_body::<PasskeyLoginRequest>())
+        .and(filters::with_db(db))
+        .and(filters::with_webauthn(webauthn))
+        .and(filters::client_info())
+        .and_then(passkey_handler::log_in_with_passkey)
+}
+
+pub fn passkey_registration_options(
+    db: Db,
+    webauthn: SharedWebauthn,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "passkeys" / "options")
+        .and(warp::post())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_webauthn(webauthn))
+        .and(filters::with_own_auth(db, true))
+        .and_then(passkey_handler::registration_options)
+}
+
+pub fn register_passkey(
+    db: Db,
+    webauthn: SharedWebauthn,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "passkeys")
+        .and(warp::post())
+        .and(filters::json_body::<RegisterPasskeyRequest>())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_webauthn(webauthn))
+        .and(filters::with_own_auth(db, true))
+        .and_then(passkey_handler::register_passkey)
+}
+
+pub fn list_passkeys(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "passkeys")
+        .and(warp::get())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, true))
+        .and_then(passkey_handler::list_passkeys)
+}
+
+pub fn delete_passkey(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "passkeys" / String)
+        .and(warp::delete())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, true))
+        .and_then(passkey_handler::delete_passkey)
+}
+
+pub fn change_password(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "password")
+        .and(warp::put())
+        .and(filters::json_body::<ChangePasswordRequest>())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_own_claims(db, true))
+        .and_then(user_handler::change_password)
+}
+
+pub fn list_sessions(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "sessions")
+        .and(warp::get())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_claims(db, true))
+        .and_then(session_handler::list_sessions)
+}
+
+pub fn revoke_session(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "sessions" / i32)
+        .and(warp::delete())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_claims(db, true))
+        .and_then(session_handler::revoke_session)
+}
+
+pub fn enroll_totp(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "mfa" / "totp")
+        .and(warp::post())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_own_auth(db, true))
+        .and_then(mfa_handler::enroll_totp)
+}
+
+pub fn confirm_totp(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "mfa" / "totp" / "confirm")
+        .and(warp::post())
+        .and(filters::json_body::<MfaCodeRequest>())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_own_auth(db, true))
+        .and_then(mfa_handler::confirm_totp)
+}
+
+pub fn disable_totp(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "mfa" / "totp")
+        .and(warp::delete())
+        .and(filters::json_body::<MfaCodeRequest>())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, true))
+        .and_then(mfa_handler::disable_totp)
+}
+
+pub fn regenerate_recovery_codes(
+    db: Db,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "mfa" / "recovery-codes")
+        .and(warp::post())
+        .and(filters::json_body::<MfaCodeRequest>())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_own_auth(db, true))
+        .and_then(mfa_handler::regenerate_recovery_codes)
+}
+
+pub fn request_password_reset(
+    db: Db,
+    mailer: SharedMailer,
+    throttle: SharedThrottle,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("password" / "reset")
+        .and(warp::post())
+        .and(filters::json_body::<PasswordResetRequest>())
+        .and(filters::with_db(db))
+        .and(filters::with_mailer(mailer))
+        .and(filters::with_throttle(throttle))
+        .and(filters::client_info())
+        .and_then(password_reset_handler::request_password_reset)
+}
+
+pub fn reset_password(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("password" / "reset" / "confirm")
+        .and(warp::post())
+        .and(filters::json_body::<ResetPasswordRequest>())
+        .and(filters::with_db(db))
+        .and_then(password_reset_handler::reset_password)
+}
+
+pub fn request_email_verification(
+    db: Db,
+    mailer: SharedMailer,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "email" / "verification")
+        .and(warp::post())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_mailer(mailer))
+        .and(filters::with_own_auth(db, true))
+        .and_then(email_verification_handler::request_verification)
+}
+
+pub fn verify_email(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("email" / "verify")
+        .and(warp::post())
+        .and(filters::json_body::<VerifyEmailRequest>())
+        .and(filters::with_db(db))
+        .and_then(email_verification_handler::verify_email)
+}
+
+pub fn get_profile(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me")
+        .and(warp::get())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, true))
+        .and_then(profile_handler::get_profile)
+}
+
+pub fn update_profile(
+    db: Db,
+    mailer: SharedMailer,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me")
+        .and(warp::patch())
+        .and(filters::json_body::<UpdateProfileRequest>())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_mailer(mailer))
+        .and(filters::with_own_auth(db, true))
+        .and_then(profile_handler::update_profile)
+}
+
+pub fn delete_account(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me")
+        .and(warp::delete())
+        .and(warp::query::<DeleteAccountQuery>())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_own_auth(db, true))
+        .and_then(profile_handler::delete_account)
+}
+
+pub fn export_account(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("me" / "export")
+        .and(warp::get())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_auth(db, true))
+        .and_then(profile_handler::export_account)
+}
+
+pub fn admin_search_users(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("admin" / "users")
+        .and(warp::get())
+        .and(warp::query::<UserSearchQuery>())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_system_admin(db))
+        .and_then(system_admin_handler::search_users)
+}
+
+pub fn admin_get_user(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("admin" / "users" / i32)
+        .and(warp::get())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_system_admin(db))
+        .and_then(system_admin_handler::get_user)
+}
+
+pub fn admin_disable_user(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("admin" / "users" / i32 / "disable")
+        .and(warp::post())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_system_admin(db))
+        .and_then(system_admin_handler::disable_user)
+}
+
+pub fn admin_enable_user(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("admin" / "users" / i32 / "enable")
+        .and(warp::post())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_system_admin(db))
+        .and_then(system_admin_handler::enable_user)
+}
+
+pub fn admin_force_password_reset(
+    db: Db,
+    mailer: SharedMailer,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("admin" / "users" / i32 / "password-reset")
+        .and(warp::post())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_mailer(mailer))
+        .and(filters::with_system_admin(db))
+        .and_then(system_admin_handler::force_password_reset)
+}
+
+pub fn admin_impersonate_user(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("admin" / "users" / i32 / "impersonate")
+        .and(warp::post())
+        .and(filters::json_body::<ImpersonationRequest>())
+        .and(filters::with_db(db.clone()))
+        .and(filters::client_info())
+        .and(filters::with_system_admin(db))
+        .and_then(system_admin_handler::impersonate_user)
+}
+
+pub fn admin_reassign_parking(db: Db) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("admin" / "parkings" / i32 / "admin")
+        .and(warp::put())
+        .and(filters::json_body::<ReassignParkingRequest>())
+        .and(filters::with_db(db.clone()))
+        .and(filters::with_system_admin(db))
+        .and_then(system_admin_handler::reassign_parking)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::connection::establish_connection;
+    use crate::db::db_schema::users;
+    use crate::handlers::session_handler::{open_impersonation_session, open_session};
+    use crate::mail::file::FileMailer;
+    use crate::models::session::ClientInfo;
+    use crate::security::keys::{self, JwtKey, KeyRing};
+    use chrono::Duration;
+    use diesel::*;
+    use jsonwebtoken::Algorithm;
+    use serde_json::json;
+    use std::ops::Deref;
+    use warp::http::StatusCode;
+
+    fn create_user(db_conn: &PgConnection, login: &str) -> i32 {
+        insert_into(users::dsl::users)
+            .values((users::dsl::login.eq(Some(login)), users::dsl::guest.eq(false)))
+            .returning(users::dsl::user_id)
+            .get_result(db_conn)
+            .unwrap()
+    }
+
+    fn email_of(db_conn: &PgConnection, user_id: i32) -> Option<String> {
+        users::dsl::users.find(user_id).select(users::dsl::email).first(db_conn).unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database in DATABASE_URL"]
+    async fn impersonation_tokens_cannot_change_the_email_address() {
+        keys::install(KeyRing::single(JwtKey::from_secret(Algorithm::HS512, b"route tests")));
+        let db: Db = Arc::new(Mutex::new(establish_connection()));
+        let mailer: SharedMailer = Arc::new(FileMailer::new(Some("/dev/null".to_string())));
+        let suffix = security::generate_code(8);
+        let (user_id, admin_id, own_token, impersonation_token) = {
+            let db_conn = db.lock().unwrap();
+            let user_id = create_user(db_conn.deref(), &format!("user-{}", suffix));
+            let admin_id = create_user(db_conn.deref(), &format!("admin-{}", suffix));
+            let client = ClientInfo::default();
+            let own_token = open_session(db_conn.deref(), user_id, &client).unwrap();
+            let impersonation_token =
+                open_impersonation_session(db_conn.deref(), user_id, admin_id, false, Duration::minutes(5), &client)
+                    .unwrap();
+            (user_id, admin_id, own_token, impersonation_token)
+        };
+        let routes = update_profile(db.clone(), mailer).recover(error_handler::handle_rejection);
+        let change_email = |token: &str| {
+            warp::test::request()
+                .method("PATCH")
+                .path("/me")
+                .header("authorization", format!("Bearer {}", token))
+                .json(&json!({ "email": format!("{}@example.com", suffix.to_lowercase()) }))
+        };
+
+        let refused = change_email(&impersonation_token).reply(&routes).await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        assert_eq!(email_of(db.lock().unwrap().deref(), user_id), None);
+
+        let accepted = change_email(&own_token).reply(&routes).await;
+        assert_eq!(accepted.status(), StatusCode::OK);
+        assert!(email_of(db.lock().unwrap().deref(), user_id).is_some());
+
+        let db_conn = db.lock().unwrap();
+        diesel::delete(users::dsl::users.filter(users::dsl::user_id.eq_any(vec![user_id, admin_id])))
+            .execute(db_conn.deref())
+            .unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+use std::env;
+
+use ring::constant_time::verify_slices_are_equal;
+use warp::http::header::{COOKIE, SET_COOKIE};
+use warp::http::{HeaderMap, HeaderValue};
+use warp::reply::Response;
+
+use crate::security::token_lifetime;
+
+/// HttpOnly cookie carrying the session JWT for browser clients.
+pub const SESSION_COOKIE: &str = "session";
+/// Readable by scripts so the front end can echo it in `CSRF_HEADER` (double-submit).
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+/// Ties a single sign-on callback to the browser that started the flow.
+pub const OIDC_STATE_COOKIE: &str = "oidc_state";
+
+/// `Secure` is only dropped with `SESSION_COOKIE_SECURE=false`, for local development over plain HTTP.
+fn attributes(max_age: i64) -> String {
+    let secure = env::var("SESSION_COOKIE_SECURE").map(|v| v != "false").unwrap_or(true);
+    let same_site = env::var("SESSION_COOKIE_SAMESITE").unwrap_or_else(|_| "Lax".to_string());
+    let mut attributes = format!("Path=/; Max-Age={}; SameSite={}", max_age, same_site);
+    if let Ok(domain) = env::var("SESSION_COOKIE_DOMAIN") {
+        attributes.push_str(&format!("; Domain={}", domain));
+    }
+    if secure {
+        attributes.push_str("; Secure");
+    }
+    attributes
+}
+
+fn set_cookies(response: &mut Response, cookies: &[String]) {
+    for cookie in cookies.iter() {
+        match HeaderValue::from_str(cookie) {
+            Ok(value) => {
+                response.headers_mut().append(SET_COOKIE, value);
+            }
+            Err(_) => eprintln!("invalid cookie settings, check SESSION_COOKIE_DOMAIN and SESSION_COOKIE_SAMESITE"),
+        }
+    }
+}
+
+/// Starts a cookie session: the session token and its CSRF token.
+pub fn add_session_cookies(response: &mut Response, token: &str, csrf_token: &str) {
+    let max_age = token_lifetime().num_seconds();
+    set_cookies(
+        response,
+        &[
+            format!("{}={}; HttpOnly; {}", SESSION_COOKIE, token, attributes(max_age)),
+            format!("{}={}; {}", CSRF_COOKIE, csrf_token, attributes(max_age)),
+        ],
+    );
+}
+
+/// Makes the browser drop both cookies.
+pub fn add_expired_cookies(response: &mut Response) {
+    set_cookies(
+        response,
+        &[
+            format!("{}=; HttpOnly; {}", SESSION_COOKIE, attributes(0)),
+            format!("{}=; {}", CSRF_COOKIE, attributes(0)),
+        ],
+    );
+}
+
+/// Remembers the state of a single sign-on flow for as long as the flow may take.
+pub fn add_oidc_state_cookie(response: &mut Response, state: &str, max_age: i64) {
+    set_cookies(
+        response,
+        &[format!("{}={}; HttpOnly; {}", OIDC_STATE_COOKIE, state, attributes(max_age))],
+    );
+}
+
+pub fn add_expired_oidc_state_cookie(response: &mut Response) {
+    set_cookies(response, &[format!("{}=; HttpOnly; {}", OIDC_STATE_COOKIE, attributes(0))]);
+}
+
+pub fn cookie_value(headers: &HeaderMap<HeaderValue>, name: &str) -> Option<String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+/// The CSRF header must be present and match the CSRF cookie; a cross-site page can make the
+/// browser send the cookie but cannot read it to set the header.
+pub fn csrf_token_matches(headers: &HeaderMap<HeaderValue>) -> bool {
+    let cookie = cookie_value(headers, CSRF_COOKIE);
+    let header = headers.get(CSRF_HEADER).and_then(|h| h.to_str().ok());
+    match (cookie, header) {
+        (Some(cookie), Some(header)) if !cookie.is_empty() => {
+            verify_slices_are_equal(cookie.as_bytes(), header.as_bytes()).is_ok()
+        }
+        _ => false,
+    }
+}
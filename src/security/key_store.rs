@@ -0,0 +1,305 @@
+use chrono::{DateTime, Utc};
+use jsonwebtoken::Algorithm;
+use rand::Rng;
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::security::keys::{parse_algorithm, JwtKey, KeyError, KeyRing};
+
+const MANIFEST: &str = "keys.json";
+
+// DER prefixes of SubjectPublicKeyInfo structures for fixed-size public keys
+const P256_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+const ED25519_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// `keys.json` in `JWT_KEYS_DIR`: every listed key verifies tokens, the active one also signs them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyManifest {
+    pub active: Option<String>,
+    pub keys: Vec<KeyEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyEntry {
+    pub kid: String,
+    pub algorithm: String,
+    /// PEM private key, or the raw secret for HMAC keys, relative to the keys directory.
+    pub private_key: String,
+    pub public_key: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+fn invalid_manifest<E: ToString>(e: E) -> KeyError {
+    KeyError::InvalidManifest(e.to_string())
+}
+
+pub fn read_manifest(dir: &str) -> Result<KeyManifest, KeyError> {
+    let path = Path::new(dir).join(MANIFEST);
+    if !path.exists() {
+        return Ok(KeyManifest::default());
+    }
+    let content = fs::read(&path).map_err(|e| KeyError::UnreadableFile(path.display().to_string(), e))?;
+    serde_json::from_slice(&content).map_err(invalid_manifest)
+}
+
+/// Writes through a temporary file so running instances never read a half-written manifest.
+pub fn write_manifest(dir: &str, manifest: &KeyManifest) -> Result<(), KeyError> {
+    let path = Path::new(dir).join(MANIFEST);
+    let tmp = Path::new(dir).join(format!("{}.tmp", MANIFEST));
+    let content = serde_json::to_vec_pretty(manifest).map_err(invalid_manifest)?;
+    fs::write(&tmp, content).map_err(|e| KeyError::UnreadableFile(tmp.display().to_string(), e))?;
+    fs::rename(&tmp, &path).map_err(|e| KeyError::UnreadableFile(path.display().to_string(), e))
+}
+
+pub fn load(dir: &str) -> Result<KeyRing, KeyError> {
+    let manifest = read_manifest(dir)?;
+    let active = manifest
+        .active
+        .clone()
+        .ok_or_else(|| KeyError::InvalidManifest("no active key".to_string()))?;
+    let keys = manifest
+        .keys
+        .iter()
+        .map(|entry| load_entry(dir, entry))
+        .collect::<Result<Vec<JwtKey>, KeyError>>()?;
+    KeyRing::new(&active, keys)
+}
+
+fn read_key_file(dir: &str, file: &str) -> Result<Vec<u8>, KeyError> {
+    let path = Path::new(dir).join(file);
+    fs::read(&path).map_err(|e| KeyError::UnreadableFile(path.display().to_string(), e))
+}
+
+fn load_entry(dir: &str, entry: &KeyEntry) -> Result<JwtKey, KeyError> {
+    let algorithm = parse_algorithm(&entry.algorithm)?;
+    let private_key = read_key_file(dir, &entry.private_key)?;
+    let key = match &entry.public_key {
+        None => JwtKey::from_secret(algorithm, &private_key),
+        Some(public_key) => {
+            JwtKey::from_pem(algorithm, &private_key, &read_key_file(dir, public_key)?)?
+        }
+    };
+    Ok(key.with_kid(entry.kid.clone()))
+}
+
+fn new_kid() -> String {
+    format!(
+        "{}-{}",
+        Utc::now().format("%Y%m%d"),
+        hex::encode(rand::thread_rng().gen::<[u8; 4]>())
+    )
+}
+
+fn to_pem(tag: &str, contents: Vec<u8>) -> Vec<u8> {
+    pem::encode(&pem::Pem {
+        tag: tag.to_string(),
+        contents,
+    })
+    .into_bytes()
+}
+
+/// Returns the private and public key files' contents for a freshly generated key.
+pub fn generate_material(algorithm: Algorithm) -> Result<(Vec<u8>, Option<Vec<u8>>), KeyError> {
+    let rng = SystemRandom::new();
+    let failed = |_| KeyError::InvalidKey("key generation failed".to_string());
+    match algorithm {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+            let mut secret = [0u8; 64];
+            rand::thread_rng().fill(&mut secret[..]);
+            Ok((hex::encode(secret).into_bytes(), None))
+        }
+        Algorithm::ES256 => {
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                .map_err(failed)?;
+            let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref())
+                .map_err(|e| KeyError::InvalidKey(e.to_string()))?;
+            let spki = [P256_SPKI_PREFIX, pair.public_key().as_ref()].concat();
+            Ok((to_pem("PRIVATE KEY", pkcs8.as_ref().to_vec()), Some(to_pem("PUBLIC KEY", spki))))
+        }
+        Algorithm::EdDSA => {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).map_err(failed)?;
+            let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+                .map_err(|e| KeyError::InvalidKey(e.to_string()))?;
+            let spki = [ED25519_SPKI_PREFIX, pair.public_key().as_ref()].concat();
+            Ok((to_pem("PRIVATE KEY", pkcs8.as_ref().to_vec()), Some(to_pem("PUBLIC KEY", spki))))
+        }
+        other => Err(KeyError::UnsupportedAlgorithm(format!(
+            "{:?} keys cannot be generated here, create them with openssl and import them",
+            other
+        ))),
+    }
+}
+
+/// Adds a key to the manifest without activating it, unless there is no active key yet.
+/// Imported keys keep the kid they are given in single-key mode, so tokens signed before
+/// the move to `JWT_KEYS_DIR` still find their key.
+fn stage(
+    dir: &str,
+    algorithm: Algorithm,
+    private_key: Vec<u8>,
+    public_key: Option<Vec<u8>>,
+    imported: bool,
+) -> Result<String, KeyError> {
+    let mut manifest = read_manifest(dir)?;
+
+    // refuse material that the next reload would not be able to load
+    let key = match (&public_key, algorithm) {
+        (None, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) => JwtKey::from_secret(algorithm, &private_key),
+        (Some(public_key), _) => JwtKey::from_pem(algorithm, &private_key, public_key)?,
+        (None, _) => return Err(KeyError::InvalidKey("a public key is required".to_string())),
+    };
+    let kid = if imported { key.kid } else { new_kid() };
+    if manifest.keys.iter().any(|entry| entry.kid == kid) {
+        return Err(KeyError::InvalidManifest(format!("key {} is already listed", kid)));
+    }
+    let private_file = format!("{}.key", kid);
+    let public_file = public_key.as_ref().map(|_| format!("{}.pub", kid));
+
+    fs::create_dir_all(dir).map_err(|e| KeyError::UnreadableFile(dir.to_string(), e))?;
+    write_key_file(dir, &private_file, &private_key)?;
+    if let (Some(file), Some(contents)) = (&public_file, &public_key) {
+        write_key_file(dir, file, contents)?;
+    }
+    manifest.keys.push(KeyEntry {
+        kid: kid.clone(),
+        algorithm: format!("{:?}", algorithm),
+        private_key: private_file,
+        public_key: public_file,
+        created_at: Utc::now(),
+    });
+    if manifest.active.is_none() {
+        manifest.active = Some(kid.clone());
+    }
+    write_manifest(dir, &manifest)?;
+    Ok(kid)
+}
+
+fn write_key_file(dir: &str, file: &str, contents: &[u8]) -> Result<(), KeyError> {
+    let path: PathBuf = Path::new(dir).join(file);
+    fs::write(&path, contents).map_err(|e| KeyError::UnreadableFile(path.display().to_string(), e))?;
+    restrict_permissions(&path);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    let _ = fs::set_permissions(path, fs::Permissions::from_mode(0o600));
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) {}
+
+pub fn generate(dir: &str, algorithm: Algorithm) -> Result<String, KeyError> {
+    let (private_key, public_key) = generate_material(algorithm)?;
+    stage(dir, algorithm, private_key, public_key, false)
+}
+
+pub fn import(
+    dir: &str,
+    algorithm: Algorithm,
+    private_key_path: &str,
+    public_key_path: Option<&str>,
+) -> Result<String, KeyError> {
+    let read = |path: &str| {
+        fs::read(path).map_err(|e| KeyError::UnreadableFile(path.to_string(), e))
+    };
+    let public_key = match public_key_path {
+        Some(path) => Some(read(path)?),
+        None => None,
+    };
+    stage(dir, algorithm, read(private_key_path)?, public_key, true)
+}
+
+pub fn activate(dir: &str, kid: &str) -> Result<(), KeyError> {
+    let mut manifest = read_manifest(dir)?;
+    if !manifest.keys.iter().any(|entry| entry.kid == kid) {
+        return Err(KeyError::UnknownKey(kid.to_string()));
+    }
+    manifest.active = Some(kid.to_string());
+    write_manifest(dir, &manifest)
+}
+
+/// Drops a key from verification; its files are left in place for the operator to remove.
+pub fn retire(dir: &str, kid: &str) -> Result<(), KeyError> {
+    let mut manifest = read_manifest(dir)?;
+    if manifest.active.as_deref() == Some(kid) {
+        return Err(KeyError::InvalidManifest("the active key cannot be retired".to_string()));
+    }
+    let before = manifest.keys.len();
+    manifest.keys.retain(|entry| entry.kid != kid);
+    if manifest.keys.len() == before {
+        return Err(KeyError::UnknownKey(kid.to_string()));
+    }
+    write_manifest(dir, &manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{decode, encode, Header, Validation};
+    use serde_json::{json, Value};
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, hex::encode(rand::thread_rng().gen::<[u8; 4]>())));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn sign(key: &JwtKey) -> String {
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(key.kid.clone());
+        encode(&header, &json!({ "sub": "1", "exp": 4102444800u64 }), &key.encoding_key).unwrap()
+    }
+
+    fn verifies(keys: &KeyRing, token: &str) -> bool {
+        let header = jsonwebtoken::decode_header(token).unwrap();
+        match keys.find(header.kid.as_deref()) {
+            Some(key) => decode::<Value>(token, &key.decoding_key, &Validation::new(key.algorithm)).is_ok(),
+            None => false,
+        }
+    }
+
+    #[test]
+    fn single_key_tokens_survive_the_move_to_a_key_directory() {
+        let scratch = scratch_dir("jwt-secret");
+        let secret_file = scratch.join("secret");
+        fs::write(&secret_file, b"an existing JWT_SECRET").unwrap();
+        let token = sign(&JwtKey::from_secret(Algorithm::HS512, b"an existing JWT_SECRET"));
+
+        let dir = scratch.join("keys");
+        let dir = dir.to_str().unwrap();
+        import(dir, Algorithm::HS512, secret_file.to_str().unwrap(), None).unwrap();
+        assert!(verifies(&load(dir).unwrap(), &token));
+
+        // still accepted once a newer key signs
+        let kid = generate(dir, Algorithm::ES256).unwrap();
+        activate(dir, &kid).unwrap();
+        let keys = load(dir).unwrap();
+        assert_eq!(keys.active().kid, kid);
+        assert!(verifies(&keys, &token));
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[test]
+    fn importing_the_same_key_twice_is_refused() {
+        let scratch = scratch_dir("jwt-secret");
+        let secret_file = scratch.join("secret");
+        fs::write(&secret_file, b"another secret").unwrap();
+        let dir = scratch.join("keys");
+        let dir = dir.to_str().unwrap();
+        import(dir, Algorithm::HS512, secret_file.to_str().unwrap(), None).unwrap();
+        assert!(import(dir, Algorithm::HS512, secret_file.to_str().unwrap(), None).is_err());
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+}
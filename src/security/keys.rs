@@ -0,0 +1,310 @@
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+    EllipticCurveKeyType, Jwk, JwkSet, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use sha2::{Digest, Sha256};
+use simple_asn1::ASN1Block;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+use std::{env, fs};
+use thiserror::Error;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::security::key_store;
+
+static CURRENT_KEYS: OnceLock<RwLock<Arc<KeyRing>>> = OnceLock::new();
+
+#[derive(Error, Debug)]
+pub enum KeyError {
+    #[error("{0} must be set")]
+    MissingSetting(&'static str),
+    #[error("unsupported JWT algorithm {0}")]
+    UnsupportedAlgorithm(String),
+    #[error("cannot read key file {0}: {1}")]
+    UnreadableFile(String, std::io::Error),
+    #[error("invalid key: {0}")]
+    InvalidKey(String),
+    #[error("unknown key id {0}")]
+    UnknownKey(String),
+    #[error("invalid key manifest: {0}")]
+    InvalidManifest(String),
+}
+
+pub struct JwtKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub encoding_key: EncodingKey,
+    pub decoding_key: DecodingKey,
+    /// Public half published on the JWKS endpoint; `None` for shared HMAC secrets.
+    pub jwk: Option<Jwk>,
+}
+
+impl JwtKey {
+    pub fn from_secret(algorithm: Algorithm, secret: &[u8]) -> JwtKey {
+        JwtKey {
+            kid: key_id(secret),
+            algorithm,
+            encoding_key: EncodingKey::from_secret(secret),
+            decoding_key: DecodingKey::from_secret(secret),
+            jwk: None,
+        }
+    }
+
+    /// Builds an asymmetric key from a PKCS#8 private key and its SubjectPublicKeyInfo public key.
+    pub fn from_pem(
+        algorithm: Algorithm,
+        private_pem: &[u8],
+        public_pem: &[u8],
+    ) -> Result<JwtKey, KeyError> {
+        let invalid = |e: jsonwebtoken::errors::Error| KeyError::InvalidKey(e.to_string());
+        let (encoding_key, decoding_key) = match algorithm {
+            Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 => (
+                EncodingKey::from_rsa_pem(private_pem).map_err(invalid)?,
+                DecodingKey::from_rsa_pem(public_pem).map_err(invalid)?,
+            ),
+            Algorithm::ES256 | Algorithm::ES384 => (
+                EncodingKey::from_ec_pem(private_pem).map_err(invalid)?,
+                DecodingKey::from_ec_pem(public_pem).map_err(invalid)?,
+            ),
+            Algorithm::EdDSA => (
+                EncodingKey::from_ed_pem(private_pem).map_err(invalid)?,
+                DecodingKey::from_ed_pem(public_pem).map_err(invalid)?,
+            ),
+            other => return Err(KeyError::UnsupportedAlgorithm(format!("{:?}", other))),
+        };
+        let public = pem::parse(public_pem).map_err(|e| KeyError::InvalidKey(e.to_string()))?;
+        let kid = key_id(&public.contents);
+        let jwk = public_jwk(algorithm, &kid, &public)?;
+        Ok(JwtKey {
+            kid,
+            algorithm,
+            encoding_key,
+            decoding_key,
+            jwk: Some(jwk),
+        })
+    }
+
+    pub fn with_kid(mut self, kid: String) -> JwtKey {
+        if let Some(jwk) = self.jwk.as_mut() {
+            jwk.common.key_id = Some(kid.clone());
+        }
+        self.kid = kid;
+        self
+    }
+}
+
+/// Every key accepted for verification, one of which signs new tokens.
+pub struct KeyRing {
+    active: Arc<JwtKey>,
+    keys: HashMap<String, Arc<JwtKey>>,
+}
+
+impl KeyRing {
+    pub fn single(key: JwtKey) -> KeyRing {
+        let key = Arc::new(key);
+        let mut keys = HashMap::new();
+        keys.insert(key.kid.clone(), key.clone());
+        KeyRing { active: key, keys }
+    }
+
+    pub fn new(active_kid: &str, keys: Vec<JwtKey>) -> Result<KeyRing, KeyError> {
+        let keys: HashMap<String, Arc<JwtKey>> = keys
+            .into_iter()
+            .map(|key| (key.kid.clone(), Arc::new(key)))
+            .collect();
+        let active = keys
+            .get(active_kid)
+            .cloned()
+            .ok_or_else(|| KeyError::UnknownKey(active_kid.to_string()))?;
+        Ok(KeyRing { active, keys })
+    }
+
+    pub fn active(&self) -> Arc<JwtKey> {
+        self.active.clone()
+    }
+
+    /// Tokens without a `kid` header predate rotation and are checked against the active key.
+    pub fn find(&self, kid: Option<&str>) -> Option<Arc<JwtKey>> {
+        match kid {
+            Some(kid) => self.keys.get(kid).cloned(),
+            None => Some(self.active()),
+        }
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        let mut keys: Vec<Jwk> = self.keys.values().filter_map(|key| key.jwk.clone()).collect();
+        keys.sort_by(|a, b| a.common.key_id.cmp(&b.common.key_id));
+        JwkSet { keys }
+    }
+}
+
+/// Loads the key manifest from `JWT_KEYS_DIR` when it is set, otherwise a single key from
+/// `JWT_ALGORITHM` (HS512 by default) with `JWT_SECRET` or the PEM files in
+/// `JWT_PRIVATE_KEY_FILE` and `JWT_PUBLIC_KEY_FILE`.
+pub fn from_env() -> Result<KeyRing, KeyError> {
+    match env::var("JWT_KEYS_DIR") {
+        Ok(dir) => key_store::load(&dir),
+        Err(_) => single_key_from_env().map(KeyRing::single),
+    }
+}
+
+fn single_key_from_env() -> Result<JwtKey, KeyError> {
+    let algorithm_name = env::var("JWT_ALGORITHM").unwrap_or_else(|_| "HS512".to_string());
+    let algorithm = parse_algorithm(&algorithm_name)?;
+    match algorithm {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+            let secret =
+                env::var("JWT_SECRET").map_err(|_| KeyError::MissingSetting("JWT_SECRET"))?;
+            Ok(JwtKey::from_secret(algorithm, secret.as_bytes()))
+        }
+        _ => {
+            let private_pem = read_setting_file("JWT_PRIVATE_KEY_FILE")?;
+            let public_pem = read_setting_file("JWT_PUBLIC_KEY_FILE")?;
+            JwtKey::from_pem(algorithm, &private_pem, &public_pem)
+        }
+    }
+}
+
+fn read_setting_file(setting: &'static str) -> Result<Vec<u8>, KeyError> {
+    let path = env::var(setting).map_err(|_| KeyError::MissingSetting(setting))?;
+    fs::read(&path).map_err(|e| KeyError::UnreadableFile(path, e))
+}
+
+pub fn install(keys: KeyRing) {
+    let keys = Arc::new(keys);
+    if let Err(lock) = CURRENT_KEYS.set(RwLock::new(keys.clone())) {
+        *lock.write().unwrap() = keys;
+    }
+}
+
+pub fn current() -> Arc<KeyRing> {
+    CURRENT_KEYS
+        .get()
+        .expect("JWT keys not installed")
+        .read()
+        .unwrap()
+        .clone()
+}
+
+pub fn jwks() -> JwkSet {
+    current().jwks()
+}
+
+/// Reloads `JWT_KEYS_DIR` on SIGHUP and every `JWT_KEYS_RELOAD_SECS` (300 by default),
+/// keeping the previous keys when the manifest cannot be read.
+pub async fn watch() {
+    if env::var("JWT_KEYS_DIR").is_err() {
+        return;
+    }
+    let interval = env::var("JWT_KEYS_RELOAD_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(300);
+    let mut hangup = signal(SignalKind::hangup()).expect("Error installing SIGHUP handler");
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => {},
+            _ = tokio::time::sleep(Duration::from_secs(interval)) => {},
+        }
+        match from_env() {
+            Ok(keys) => install(keys),
+            Err(e) => eprintln!("JWT keys not reloaded: {}", e),
+        }
+    }
+}
+
+pub fn parse_algorithm(name: &str) -> Result<Algorithm, KeyError> {
+    Algorithm::from_str(name).map_err(|_| KeyError::UnsupportedAlgorithm(name.to_string()))
+}
+
+fn key_id(material: &[u8]) -> String {
+    base64::encode_config(&Sha256::digest(material)[..12], base64::URL_SAFE_NO_PAD)
+}
+
+fn b64(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn public_jwk(algorithm: Algorithm, kid: &str, public: &pem::Pem) -> Result<Jwk, KeyError> {
+    let parameters = match algorithm {
+        Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 => {
+            let der = if public.tag == "RSA PUBLIC KEY" {
+                public.contents.clone()
+            } else {
+                subject_public_key(&public.contents)?
+            };
+            let (n, e) = rsa_components(&der)?;
+            AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: b64(&n),
+                e: b64(&e),
+            })
+        }
+        Algorithm::ES256 | Algorithm::ES384 => {
+            let (curve, size) = match algorithm {
+                Algorithm::ES256 => (EllipticCurve::P256, 32),
+                _ => (EllipticCurve::P384, 48),
+            };
+            let point = subject_public_key(&public.contents)?;
+            if point.len() != 1 + 2 * size || point[0] != 0x04 {
+                return Err(KeyError::InvalidKey("expected an uncompressed EC point".to_string()));
+            }
+            AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                key_type: EllipticCurveKeyType::EC,
+                curve,
+                x: b64(&point[1..1 + size]),
+                y: b64(&point[1 + size..]),
+            })
+        }
+        _ => {
+            let x = subject_public_key(&public.contents)?;
+            if x.len() != 32 {
+                return Err(KeyError::InvalidKey("expected an Ed25519 public key".to_string()));
+            }
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: b64(&x),
+            })
+        }
+    };
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            algorithm: Some(algorithm),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    })
+}
+
+/// Extracts the key bit string from a DER encoded SubjectPublicKeyInfo.
+fn subject_public_key(der: &[u8]) -> Result<Vec<u8>, KeyError> {
+    let blocks = simple_asn1::from_der(der).map_err(|e| KeyError::InvalidKey(e.to_string()))?;
+    match blocks.first() {
+        Some(ASN1Block::Sequence(_, fields)) => match fields.get(1) {
+            Some(ASN1Block::BitString(_, _, bits)) => Ok(bits.clone()),
+            _ => Err(KeyError::InvalidKey("missing public key bit string".to_string())),
+        },
+        _ => Err(KeyError::InvalidKey("expected a SubjectPublicKeyInfo".to_string())),
+    }
+}
+
+/// Returns the big-endian modulus and exponent of a DER encoded RSAPublicKey.
+fn rsa_components(der: &[u8]) -> Result<(Vec<u8>, Vec<u8>), KeyError> {
+    let blocks = simple_asn1::from_der(der).map_err(|e| KeyError::InvalidKey(e.to_string()))?;
+    match blocks.first() {
+        Some(ASN1Block::Sequence(_, fields)) => match (fields.first(), fields.get(1)) {
+            (Some(ASN1Block::Integer(_, n)), Some(ASN1Block::Integer(_, e))) => {
+                Ok((n.to_bytes_be().1, e.to_bytes_be().1))
+            }
+            _ => Err(KeyError::InvalidKey("expected RSA modulus and exponent".to_string())),
+        },
+        _ => Err(KeyError::InvalidKey("expected an RSAPublicKey".to_string())),
+    }
+}
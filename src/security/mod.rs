@@ -1,40 +1,121 @@
-use argon2::{self, Config};
 use chrono::prelude::*;
-use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use jsonwebtoken::{decode, decode_header, encode, Header, Validation};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use crate::handlers::error_handler::Error;
 
+pub mod cookie;
+pub mod key_store;
+pub mod keys;
+pub mod oidc;
+pub mod password;
+pub mod throttle;
+pub mod totp;
+pub mod webauthn;
+
+pub use password::{check_password, hash, verify, verify_dummy, PasswordCheck};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Claims {
     pub id: i32,
+    pub sid: i32,
     pub exp: usize,
+    /// The system admin acting as `id`; only set in impersonation tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor_id: Option<i32>,
+    /// Limits the token to requests that do not change anything.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub read_only: bool,
+}
+
+pub fn generate_token() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; 32]>())
+}
+
+/// Characters for codes people type in: upper case letters and digits without lookalikes (0/O, 1/I/L).
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+
+/// A random code of `length` characters, grouped by four with dashes for readability.
+pub fn generate_code(length: usize) -> String {
+    let mut rng = rand::thread_rng();
+    let chars: Vec<char> = (0..length)
+        .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+        .collect();
+    chars
+        .chunks(4)
+        .map(|group| group.iter().collect::<String>())
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+/// Undoes the grouping and case differences of a typed code so it can be hashed and compared.
+pub fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_uppercase()
 }
 
-pub fn hash(password: &[u8]) -> String {
-    let salt = rand::thread_rng().gen::<[u8; 32]>();
-    let config = Config::default();
-    argon2::hash_encoded(password, &salt, &config).unwrap()
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
 
-pub fn verify(hash: &str, password: &[u8]) -> bool {
-    argon2::verify_encoded(hash, password).unwrap_or(false)
+/// How long a session token, and the cookie carrying it, stays valid.
+pub fn token_lifetime() -> chrono::Duration {
+    chrono::Duration::hours(60)
 }
 
-pub fn create_jwt(id: &i32, jwt_secret: &[u8]) -> Result<String, Error> {
-    let expiration = Utc::now()
-        .checked_add_signed(chrono::Duration::hours(60))
+fn expires_in(lifetime: chrono::Duration) -> usize {
+    Utc::now()
+        .checked_add_signed(lifetime)
         .expect("valid timestamp")
-        .timestamp();
-    let claims = Claims {
+        .timestamp() as usize
+}
+
+pub fn create_jwt(id: &i32, session_id: &i32) -> Result<String, Error> {
+    sign_claims(&Claims {
         id: *id,
-        exp: expiration as usize,
-    };
-    let header = Header::new(Algorithm::HS512);
-    encode(&header, &claims, &EncodingKey::from_secret(jwt_secret))
-        .map_err(|_| Error::JWTTokenCreationError)
+        sid: *session_id,
+        exp: expires_in(token_lifetime()),
+        actor_id: None,
+        read_only: false,
+    })
+}
+
+/// A token for `id` used by the system admin `actor_id`, valid for `lifetime` only.
+pub fn create_impersonation_jwt(
+    id: i32,
+    session_id: i32,
+    actor_id: i32,
+    read_only: bool,
+    lifetime: chrono::Duration,
+) -> Result<String, Error> {
+    sign_claims(&Claims {
+        id,
+        sid: session_id,
+        exp: expires_in(lifetime),
+        actor_id: Some(actor_id),
+        read_only,
+    })
+}
+
+fn sign_claims(claims: &Claims) -> Result<String, Error> {
+    let key = keys::current().active();
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+    encode(&header, claims, &key.encoding_key).map_err(|_| Error::JWTTokenCreationError)
+}
+
+pub fn decode_jwt(token: &str) -> Result<Claims, Error> {
+    let header = decode_header(token).map_err(|_| Error::JWTTokenError)?;
+    let key = keys::current()
+        .find(header.kid.as_deref())
+        .ok_or(Error::JWTTokenError)?;
+    decode::<Claims>(token, &key.decoding_key, &Validation::new(key.algorithm))
+        .map(|data| data.claims)
+        .map_err(|_| Error::JWTTokenError)
 }
 
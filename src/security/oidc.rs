@@ -0,0 +1,563 @@
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use rand::Rng;
+use reqwest::Url;
+use ring::constant_time::verify_slices_are_equal;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+pub const PENDING_LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+/// Starting a flow needs no account, so the number of flows in progress is capped.
+const MAX_PENDING_LOGINS: usize = 10_000;
+
+#[derive(Error, Debug)]
+pub enum OidcError {
+    #[error("single sign-on is not configured")]
+    NotConfigured,
+    #[error("unknown or expired login state")]
+    UnknownState,
+    #[error("login state does not belong to this browser")]
+    StateMismatch,
+    #[error("too many sign-ins in progress")]
+    TooManyPendingLogins,
+    #[error("identity provider request failed: {0}")]
+    ProviderError(String),
+    #[error("invalid id token: {0}")]
+    InvalidIdToken(String),
+}
+
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+    pub scopes: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    name: Option<String>,
+}
+
+struct PendingLogin {
+    code_verifier: String,
+    nonce: String,
+    link_user_id: Option<i32>,
+    started: Instant,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorizationRequest {
+    pub authorization_url: String,
+    pub state: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub name: Option<String>,
+    /// Set when the flow was started by a signed-in user who wants to link this identity.
+    pub link_user_id: Option<i32>,
+}
+
+/// Authorization code flow with PKCE against a single OpenID Connect provider.
+/// Pending logins live in memory, so the callback must reach the instance that started the flow.
+/// The callback must also come from the browser that started it: callers keep the `state` in a
+/// cookie and pass it back to `finish`.
+pub struct OidcClient {
+    config: Option<OidcConfig>,
+    http: reqwest::Client,
+    discovery: Mutex<Option<Discovery>>,
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+pub type SharedOidc = Arc<OidcClient>;
+
+fn random_token() -> String {
+    base64::encode_config(rand::thread_rng().gen::<[u8; 32]>(), base64::URL_SAFE_NO_PAD)
+}
+
+fn provider_error<E: ToString>(e: E) -> OidcError {
+    OidcError::ProviderError(e.to_string())
+}
+
+impl OidcClient {
+    /// Enabled when `OIDC_ISSUER_URL`, `OIDC_CLIENT_ID` and `OIDC_REDIRECT_URI` are set;
+    /// `OIDC_CLIENT_SECRET` is only needed for confidential clients.
+    pub fn from_env() -> OidcClient {
+        let config = match (
+            env::var("OIDC_ISSUER_URL"),
+            env::var("OIDC_CLIENT_ID"),
+            env::var("OIDC_REDIRECT_URI"),
+        ) {
+            (Ok(issuer), Ok(client_id), Ok(redirect_uri)) => Some(OidcConfig {
+                issuer: issuer.trim_end_matches('/').to_string(),
+                client_id,
+                client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+                redirect_uri,
+                scopes: env::var("OIDC_SCOPES").unwrap_or_else(|_| "openid email profile".to_string()),
+            }),
+            _ => None,
+        };
+        OidcClient::new(config)
+    }
+
+    pub fn new(config: Option<OidcConfig>) -> OidcClient {
+        OidcClient {
+            config,
+            http: reqwest::Client::new(),
+            discovery: Mutex::new(None),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn config(&self) -> Result<&OidcConfig, OidcError> {
+        self.config.as_ref().ok_or(OidcError::NotConfigured)
+    }
+
+    async fn discovery(&self) -> Result<Discovery, OidcError> {
+        if let Some(discovery) = self.discovery.lock().unwrap().clone() {
+            return Ok(discovery);
+        }
+        let config = self.config()?;
+        let url = format!("{}/.well-known/openid-configuration", config.issuer);
+        let discovery: Discovery = self
+            .http
+            .get(&url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)?;
+        if discovery.issuer.trim_end_matches('/') != config.issuer {
+            return Err(OidcError::ProviderError("issuer mismatch in discovery document".to_string()));
+        }
+        *self.discovery.lock().unwrap() = Some(discovery.clone());
+        Ok(discovery)
+    }
+
+    pub async fn start(&self, link_user_id: Option<i32>) -> Result<AuthorizationRequest, OidcError> {
+        let config = self.config()?;
+        let discovery = self.discovery().await?;
+        let state = random_token();
+        let nonce = random_token();
+        let code_verifier = random_token();
+        let code_challenge = base64::encode_config(
+            Sha256::digest(code_verifier.as_bytes()),
+            base64::URL_SAFE_NO_PAD,
+        );
+
+        let mut url = Url::parse(&discovery.authorization_endpoint).map_err(provider_error)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &config.client_id)
+            .append_pair("redirect_uri", &config.redirect_uri)
+            .append_pair("scope", &config.scopes)
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, login| login.started.elapsed() < PENDING_LOGIN_TTL);
+        if pending.len() >= MAX_PENDING_LOGINS {
+            return Err(OidcError::TooManyPendingLogins);
+        }
+        pending.insert(
+            state.clone(),
+            PendingLogin {
+                code_verifier,
+                nonce,
+                link_user_id,
+                started: Instant::now(),
+            },
+        );
+        Ok(AuthorizationRequest {
+            authorization_url: url.to_string(),
+            state,
+        })
+    }
+
+    /// Exchanges the authorization code and verifies the returned id token. `browser_state` is
+    /// the state remembered by the caller's browser, which must match the one in the callback.
+    pub async fn finish(
+        &self,
+        code: &str,
+        state: &str,
+        browser_state: Option<&str>,
+    ) -> Result<VerifiedIdentity, OidcError> {
+        let config = self.config()?;
+        let bound = browser_state.is_some_and(|browser_state| {
+            verify_slices_are_equal(browser_state.as_bytes(), state.as_bytes()).is_ok()
+        });
+        if !bound {
+            return Err(OidcError::StateMismatch);
+        }
+        let login = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(state)
+            .filter(|login| login.started.elapsed() < PENDING_LOGIN_TTL)
+            .ok_or(OidcError::UnknownState)?;
+        let discovery = self.discovery().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", config.redirect_uri.as_str()),
+            ("client_id", config.client_id.as_str()),
+            ("code_verifier", login.code_verifier.as_str()),
+        ];
+        if let Some(secret) = &config.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+        let tokens: TokenResponse = self
+            .http
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)?;
+
+        let claims = self.verify_id_token(&discovery, config, &tokens.id_token).await?;
+        if claims.nonce.as_deref() != Some(login.nonce.as_str()) {
+            return Err(OidcError::InvalidIdToken("nonce mismatch".to_string()));
+        }
+        let email_verified = claims.email_verified.unwrap_or(false);
+        Ok(VerifiedIdentity {
+            issuer: config.issuer.clone(),
+            subject: claims.sub,
+            email: claims.email.filter(|_| email_verified),
+            name: claims.name,
+            link_user_id: login.link_user_id,
+        })
+    }
+
+    async fn verify_id_token(
+        &self,
+        discovery: &Discovery,
+        config: &OidcConfig,
+        id_token: &str,
+    ) -> Result<IdTokenClaims, OidcError> {
+        let invalid = |e: jsonwebtoken::errors::Error| OidcError::InvalidIdToken(e.to_string());
+        let header = decode_header(id_token).map_err(invalid)?;
+        if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            return Err(OidcError::InvalidIdToken("symmetric signatures are not accepted".to_string()));
+        }
+        let jwks: JwkSet = self
+            .http
+            .get(&discovery.jwks_uri)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)?;
+        let jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        }
+        .ok_or_else(|| OidcError::InvalidIdToken("unknown signing key".to_string()))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&config.client_id]);
+        validation.set_issuer(&[&discovery.issuer]);
+        decode::<IdTokenClaims>(id_token, &DecodingKey::from_jwk(jwk).map_err(invalid)?, &validation)
+            .map(|data| data.claims)
+            .map_err(invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::key_store::generate_material;
+    use crate::security::keys::JwtKey;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::{json, Value};
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    const CLIENT_ID: &str = "parking-app";
+    const REDIRECT_URI: &str = "http://localhost/login/oidc/callback";
+
+    /// What the mock provider answers with; tests change it between requests.
+    struct Provider {
+        issuer: String,
+        /// The code the token endpoint accepts and the PKCE challenge it was issued for.
+        code: Option<(String, String)>,
+        claims: Value,
+        sign_with_secret: bool,
+    }
+
+    type SharedProvider = Arc<Mutex<Provider>>;
+
+    fn token_response(
+        provider: &Provider,
+        key: &JwtKey,
+        form: &HashMap<String, String>,
+    ) -> (Value, StatusCode) {
+        let (code, challenge) = match &provider.code {
+            Some(issued) => issued,
+            None => return (json!({ "error": "invalid_grant" }), StatusCode::BAD_REQUEST),
+        };
+        let verifier = form.get("code_verifier").map(String::as_str).unwrap_or_default();
+        let pkce_ok =
+            base64::encode_config(Sha256::digest(verifier.as_bytes()), base64::URL_SAFE_NO_PAD) == *challenge;
+        let request_ok = form.get("grant_type").map(String::as_str) == Some("authorization_code")
+            && form.get("code") == Some(code)
+            && form.get("client_id").map(String::as_str) == Some(CLIENT_ID)
+            && form.get("redirect_uri").map(String::as_str) == Some(REDIRECT_URI);
+        if !pkce_ok || !request_ok {
+            return (json!({ "error": "invalid_grant" }), StatusCode::BAD_REQUEST);
+        }
+        let id_token = if provider.sign_with_secret {
+            let secret = EncodingKey::from_secret(CLIENT_ID.as_bytes());
+            encode(&Header::new(Algorithm::HS256), &provider.claims, &secret)
+        } else {
+            let mut header = Header::new(key.algorithm);
+            header.kid = Some(key.kid.clone());
+            encode(&header, &provider.claims, &key.encoding_key)
+        }
+        .unwrap();
+        (json!({ "id_token": id_token, "token_type": "Bearer" }), StatusCode::OK)
+    }
+
+    /// Serves discovery, JWKS and token endpoints on an ephemeral port and returns a client for it.
+    async fn mock_provider() -> (SharedProvider, OidcClient) {
+        let (private_key, public_key) = generate_material(Algorithm::ES256).unwrap();
+        let key = JwtKey::from_pem(Algorithm::ES256, &private_key, &public_key.unwrap()).unwrap();
+        let key = Arc::new(key);
+        let provider: SharedProvider = Arc::new(Mutex::new(Provider {
+            issuer: String::new(),
+            code: None,
+            claims: Value::Null,
+            sign_with_secret: false,
+        }));
+
+        let discovery_provider = provider.clone();
+        let discovery = warp::path!(".well-known" / "openid-configuration").map(move || {
+            let issuer = discovery_provider.lock().unwrap().issuer.clone();
+            warp::reply::json(&json!({
+                "issuer": issuer,
+                "authorization_endpoint": format!("{}/authorize", issuer),
+                "token_endpoint": format!("{}/token", issuer),
+                "jwks_uri": format!("{}/jwks", issuer),
+            }))
+        });
+        let jwks_key = key.clone();
+        let jwks = warp::path!("jwks").map(move || {
+            warp::reply::json(&JwkSet {
+                keys: vec![jwks_key.jwk.clone().unwrap()],
+            })
+        });
+        let token_provider = provider.clone();
+        let token = warp::path!("token")
+            .and(warp::post())
+            .and(warp::body::form::<HashMap<String, String>>())
+            .map(move |form: HashMap<String, String>| {
+                let (body, status) = token_response(&token_provider.lock().unwrap(), &key, &form);
+                warp::reply::with_status(warp::reply::json(&body), status)
+            });
+
+        let (addr, server) = warp::serve(discovery.or(jwks).or(token)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let issuer = format!("http://{}", addr);
+        provider.lock().unwrap().issuer = issuer.clone();
+        let client = OidcClient::new(Some(OidcConfig {
+            issuer,
+            client_id: CLIENT_ID.to_string(),
+            client_secret: None,
+            redirect_uri: REDIRECT_URI.to_string(),
+            scopes: "openid email profile".to_string(),
+        }));
+        (provider, client)
+    }
+
+    /// Plays the user signing in at the provider: issues a code for the request's PKCE challenge
+    /// and prepares an id token carrying its nonce.
+    fn authorize(provider: &SharedProvider, request: &AuthorizationRequest) -> String {
+        let url = Url::parse(&request.authorization_url).unwrap();
+        let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["state"], request.state);
+        assert_eq!(params["client_id"], CLIENT_ID);
+        assert_eq!(params["code_challenge_method"], "S256");
+
+        let mut provider = provider.lock().unwrap();
+        let code = random_token();
+        provider.code = Some((code.clone(), params["code_challenge"].clone()));
+        let now = chrono::Utc::now().timestamp();
+        provider.claims = json!({
+            "iss": provider.issuer,
+            "sub": "alice",
+            "aud": CLIENT_ID,
+            "iat": now,
+            "exp": now + 300,
+            "nonce": params["nonce"],
+            "email": "alice@example.com",
+            "email_verified": true,
+            "name": "Alice",
+        });
+        code
+    }
+
+    #[tokio::test]
+    async fn exchanges_code_and_verifies_id_token() {
+        let (provider, client) = mock_provider().await;
+        let request = client.start(None).await.unwrap();
+        let code = authorize(&provider, &request);
+
+        let identity = client.finish(&code, &request.state, Some(&request.state)).await.unwrap();
+        assert_eq!(identity.issuer, provider.lock().unwrap().issuer);
+        assert_eq!(identity.subject, "alice");
+        assert_eq!(identity.email.as_deref(), Some("alice@example.com"));
+        assert_eq!(identity.name.as_deref(), Some("Alice"));
+        assert_eq!(identity.link_user_id, None);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_linking_user_and_drops_unverified_emails() {
+        let (provider, client) = mock_provider().await;
+        let request = client.start(Some(7)).await.unwrap();
+        let code = authorize(&provider, &request);
+        provider.lock().unwrap().claims["email_verified"] = json!(false);
+
+        let identity = client.finish(&code, &request.state, Some(&request.state)).await.unwrap();
+        assert_eq!(identity.link_user_id, Some(7));
+        assert_eq!(identity.email, None);
+    }
+
+    #[tokio::test]
+    async fn rejects_discovery_for_another_issuer() {
+        let (provider, client) = mock_provider().await;
+        let issuer = provider.lock().unwrap().issuer.clone();
+        provider.lock().unwrap().issuer = format!("{}/other", issuer);
+
+        assert!(matches!(client.start(None).await, Err(OidcError::ProviderError(_))));
+    }
+
+    #[tokio::test]
+    async fn rejects_callbacks_from_another_browser() {
+        let (provider, client) = mock_provider().await;
+        let request = client.start(None).await.unwrap();
+        let code = authorize(&provider, &request);
+
+        assert!(matches!(client.finish(&code, &request.state, None).await, Err(OidcError::StateMismatch)));
+        let other = client.start(None).await.unwrap();
+        assert!(matches!(
+            client.finish(&code, &request.state, Some(&other.state)).await,
+            Err(OidcError::StateMismatch)
+        ));
+        // the rejected callbacks do not cancel the flow for the browser that started it
+        assert!(client.finish(&code, &request.state, Some(&request.state)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn state_works_once() {
+        let (provider, client) = mock_provider().await;
+        let request = client.start(None).await.unwrap();
+        let code = authorize(&provider, &request);
+
+        assert!(client.finish(&code, &request.state, Some(&request.state)).await.is_ok());
+        assert!(matches!(
+            client.finish(&code, &request.state, Some(&request.state)).await,
+            Err(OidcError::UnknownState)
+        ));
+        let unknown = random_token();
+        assert!(matches!(client.finish(&code, &unknown, Some(&unknown)).await, Err(OidcError::UnknownState)));
+    }
+
+    #[tokio::test]
+    async fn rejects_nonce_mismatch() {
+        let (provider, client) = mock_provider().await;
+        let request = client.start(None).await.unwrap();
+        let code = authorize(&provider, &request);
+        provider.lock().unwrap().claims["nonce"] = json!("replayed");
+
+        assert!(matches!(
+            client.finish(&code, &request.state, Some(&request.state)).await,
+            Err(OidcError::InvalidIdToken(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_id_tokens_for_another_audience_or_issuer() {
+        let (provider, client) = mock_provider().await;
+        let request = client.start(None).await.unwrap();
+        let code = authorize(&provider, &request);
+        provider.lock().unwrap().claims["aud"] = json!("another-app");
+        assert!(matches!(
+            client.finish(&code, &request.state, Some(&request.state)).await,
+            Err(OidcError::InvalidIdToken(_))
+        ));
+
+        let request = client.start(None).await.unwrap();
+        let code = authorize(&provider, &request);
+        provider.lock().unwrap().claims["iss"] = json!("https://issuer.example");
+        assert!(matches!(
+            client.finish(&code, &request.state, Some(&request.state)).await,
+            Err(OidcError::InvalidIdToken(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_symmetrically_signed_id_tokens() {
+        let (provider, client) = mock_provider().await;
+        let request = client.start(None).await.unwrap();
+        let code = authorize(&provider, &request);
+        provider.lock().unwrap().sign_with_secret = true;
+
+        assert!(matches!(
+            client.finish(&code, &request.state, Some(&request.state)).await,
+            Err(OidcError::InvalidIdToken(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn token_exchange_needs_the_matching_code_verifier() {
+        let (provider, client) = mock_provider().await;
+        let first = client.start(None).await.unwrap();
+        let second = client.start(None).await.unwrap();
+        // the provider issued the code for the first flow's challenge
+        let code = authorize(&provider, &first);
+
+        assert!(matches!(
+            client.finish(&code, &second.state, Some(&second.state)).await,
+            Err(OidcError::ProviderError(_))
+        ));
+    }
+}
@@ -0,0 +1,202 @@
+use argon2::{Config, ThreadMode, Variant, Version};
+use rand::Rng;
+use ring::pbkdf2;
+use std::env;
+use std::num::NonZeroU32;
+use std::sync::OnceLock;
+
+static PARAMS: OnceLock<HashParams> = OnceLock::new();
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+/// Outcome of checking a password against a stored hash.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PasswordCheck {
+    Invalid,
+    Valid,
+    /// The password matches but the hash was made with older parameters, without the pepper,
+    /// or with a legacy scheme.
+    Outdated,
+}
+
+impl PasswordCheck {
+    pub fn is_valid(self) -> bool {
+        self != PasswordCheck::Invalid
+    }
+}
+
+struct HashParams {
+    mem_cost: u32,
+    time_cost: u32,
+    lanes: u32,
+    pepper: Vec<u8>,
+}
+
+fn env_u32(name: &str, default: u32) -> u32 {
+    env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+/// argon2id parameters from `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM`,
+/// defaulting to the OWASP recommendation, plus the optional server-side `ARGON2_PEPPER`.
+fn params() -> &'static HashParams {
+    PARAMS.get_or_init(|| HashParams {
+        mem_cost: env_u32("ARGON2_MEMORY_KIB", 19 * 1024),
+        time_cost: env_u32("ARGON2_ITERATIONS", 2),
+        lanes: env_u32("ARGON2_PARALLELISM", 1),
+        pepper: env::var("ARGON2_PEPPER").unwrap_or_default().into_bytes(),
+    })
+}
+
+fn config(secret: &[u8]) -> Config<'_> {
+    let params = params();
+    Config {
+        variant: Variant::Argon2id,
+        version: Version::Version13,
+        mem_cost: params.mem_cost,
+        time_cost: params.time_cost,
+        lanes: params.lanes,
+        thread_mode: ThreadMode::from_threads(params.lanes),
+        secret,
+        ..Config::default()
+    }
+}
+
+pub fn hash(password: &[u8]) -> String {
+    let salt = rand::thread_rng().gen::<[u8; 32]>();
+    argon2::hash_encoded(password, &salt, &config(&params().pepper)).unwrap()
+}
+
+/// Compares the variant and cost parameters encoded in `$argon2id$v=19$m=..,t=..,p=..$salt$hash`
+/// with the configured ones.
+fn has_current_params(encoded: &str) -> bool {
+    let config = config(&[]);
+    let expected = format!(
+        "${}$v={}$m={},t={},p={}$",
+        config.variant.as_lowercase_str(),
+        config.version.as_u32(),
+        config.mem_cost,
+        config.time_cost,
+        config.lanes
+    );
+    encoded.starts_with(&expected)
+}
+
+/// Password hash formats accepted besides our own argon2 hashes, as imported from older systems.
+enum LegacyHash<'a> {
+    Bcrypt(&'a str),
+    /// Django's `pbkdf2_sha256$iterations$salt$hash`, salt as text and hash in base64.
+    Django {
+        algorithm: pbkdf2::Algorithm,
+        iterations: NonZeroU32,
+        salt: &'a str,
+        hash: Vec<u8>,
+    },
+    /// passlib's `$pbkdf2-sha256$iterations$salt$hash` with salt and hash in adapted base64.
+    Passlib {
+        algorithm: pbkdf2::Algorithm,
+        iterations: NonZeroU32,
+        salt: Vec<u8>,
+        hash: Vec<u8>,
+    },
+}
+
+fn decode_adapted_base64(value: &str) -> Option<Vec<u8>> {
+    base64::decode_config(value.replace('.', "+"), base64::STANDARD_NO_PAD).ok()
+}
+
+fn parse_legacy_hash(hash: &str) -> Option<LegacyHash<'_>> {
+    if ["$2a$", "$2b$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix)) {
+        return Some(LegacyHash::Bcrypt(hash));
+    }
+    if let Some(rest) = hash.strip_prefix('$') {
+        let parts: Vec<&str> = rest.split('$').collect();
+        if let [scheme, iterations, salt, hash] = parts.as_slice() {
+            let algorithm = match *scheme {
+                "pbkdf2" => pbkdf2::PBKDF2_HMAC_SHA1,
+                "pbkdf2-sha256" => pbkdf2::PBKDF2_HMAC_SHA256,
+                "pbkdf2-sha512" => pbkdf2::PBKDF2_HMAC_SHA512,
+                _ => return None,
+            };
+            return Some(LegacyHash::Passlib {
+                algorithm,
+                iterations: iterations.parse().ok()?,
+                salt: decode_adapted_base64(salt)?,
+                hash: decode_adapted_base64(hash)?,
+            });
+        }
+        return None;
+    }
+    let parts: Vec<&str> = hash.split('$').collect();
+    if let [scheme, iterations, salt, hash] = parts.as_slice() {
+        let algorithm = match *scheme {
+            "pbkdf2_sha1" => pbkdf2::PBKDF2_HMAC_SHA1,
+            "pbkdf2_sha256" => pbkdf2::PBKDF2_HMAC_SHA256,
+            _ => return None,
+        };
+        return Some(LegacyHash::Django {
+            algorithm,
+            iterations: iterations.parse().ok()?,
+            salt,
+            hash: base64::decode(hash).ok()?,
+        });
+    }
+    None
+}
+
+/// Whether `hash` is in a format `check_password` understands.
+pub fn is_supported_hash(hash: &str) -> bool {
+    hash.starts_with("$argon2") || parse_legacy_hash(hash).is_some()
+}
+
+fn check_legacy_password(hash: LegacyHash, password: &[u8]) -> bool {
+    match hash {
+        LegacyHash::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+        LegacyHash::Django {
+            algorithm,
+            iterations,
+            salt,
+            hash,
+        } => pbkdf2::verify(algorithm, iterations, salt.as_bytes(), password, &hash).is_ok(),
+        LegacyHash::Passlib {
+            algorithm,
+            iterations,
+            salt,
+            hash,
+        } => pbkdf2::verify(algorithm, iterations, &salt, password, &hash).is_ok(),
+    }
+}
+
+/// Hashes made before a pepper was configured still verify without it, and are reported as outdated,
+/// as are matching bcrypt and PBKDF2 hashes.
+pub fn check_password(hash: &str, password: &[u8]) -> PasswordCheck {
+    if let Some(legacy) = parse_legacy_hash(hash) {
+        return if check_legacy_password(legacy, password) {
+            PasswordCheck::Outdated
+        } else {
+            PasswordCheck::Invalid
+        };
+    }
+    let pepper = &params().pepper;
+    if argon2::verify_encoded_ext(hash, password, pepper, &[]).unwrap_or(false) {
+        if has_current_params(hash) {
+            PasswordCheck::Valid
+        } else {
+            PasswordCheck::Outdated
+        }
+    } else if !pepper.is_empty() && argon2::verify_encoded(hash, password).unwrap_or(false) {
+        PasswordCheck::Outdated
+    } else {
+        PasswordCheck::Invalid
+    }
+}
+
+pub fn verify(hash: &str, password: &[u8]) -> bool {
+    check_password(hash, password).is_valid()
+}
+
+/// Burns the same argon2 work as `verify` for logins without a stored password,
+/// so response times do not reveal which logins are registered.
+pub fn verify_dummy(password: &[u8]) -> bool {
+    let dummy = DUMMY_HASH.get_or_init(|| hash(&rand::thread_rng().gen::<[u8; 32]>()));
+    verify(dummy, password);
+    false
+}
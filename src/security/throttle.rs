@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const MAX_TRACKED_KEYS: usize = 10_000;
+const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Hash, Eq, PartialEq, Clone, Debug)]
+pub enum AttemptKey {
+    Login(String),
+    Parking(String),
+    SecondFactor(i32),
+    /// Counts sign-in link requests per address, successful or not.
+    MagicLink(String),
+    /// Counts password reset requests per login, successful or not.
+    PasswordReset(String),
+    /// Counts wrong guesses of one pairing code, identified by the number in front of it.
+    Pairing(i32),
+    Ip(IpAddr),
+}
+
+struct Limits {
+    free_attempts: u32,
+    base_delay: Duration,
+    lockout_after: u32,
+    lockout: Duration,
+}
+
+const ACCOUNT_LIMITS: Limits = Limits {
+    free_attempts: 3,
+    base_delay: Duration::from_secs(1),
+    lockout_after: 10,
+    lockout: Duration::from_secs(15 * 60),
+};
+
+const IP_LIMITS: Limits = Limits {
+    free_attempts: 20,
+    base_delay: Duration::from_secs(1),
+    lockout_after: 100,
+    lockout: Duration::from_secs(15 * 60),
+};
+
+impl AttemptKey {
+    /// The account key followed by the caller's address, when it is known.
+    pub fn with_ip(self, ip: Option<IpAddr>) -> Vec<AttemptKey> {
+        let mut keys = vec![self];
+        keys.extend(ip.map(AttemptKey::Ip));
+        keys
+    }
+
+    fn limits(&self) -> &'static Limits {
+        match self {
+            AttemptKey::Ip(_) => &IP_LIMITS,
+            _ => &ACCOUNT_LIMITS,
+        }
+    }
+}
+
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+    blocked_until: Option<Instant>,
+}
+
+/// In-memory failed attempt counters with exponential backoff and temporary lockout.
+/// State is per process, so every instance behind a load balancer enforces its own limits.
+#[derive(Default)]
+pub struct Throttle {
+    attempts: Mutex<HashMap<AttemptKey, Attempts>>,
+}
+
+pub type SharedThrottle = Arc<Throttle>;
+
+impl Throttle {
+    /// Returns the number of seconds to wait if any of the keys is currently blocked.
+    pub fn check(&self, keys: &[AttemptKey]) -> Result<(), u64> {
+        let attempts = self.attempts.lock().unwrap();
+        let now = Instant::now();
+        let wait = keys
+            .iter()
+            .filter_map(|key| attempts.get(key))
+            .filter_map(|a| a.blocked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+            .max();
+        match wait {
+            Some(wait) => Err(wait.as_secs() + 1),
+            None => Ok(()),
+        }
+    }
+
+    pub fn record_failure(&self, keys: &[AttemptKey]) {
+        let mut attempts = self.attempts.lock().unwrap();
+        let now = Instant::now();
+        if attempts.len() > MAX_TRACKED_KEYS {
+            attempts.retain(|_, a| {
+                now.duration_since(a.last_failure) < STALE_AFTER
+                    || a.blocked_until.is_some_and(|until| until > now)
+            });
+        }
+        for key in keys {
+            let limits = key.limits();
+            let entry = attempts.entry(key.clone()).or_insert(Attempts {
+                failures: 0,
+                last_failure: now,
+                blocked_until: None,
+            });
+            if now.duration_since(entry.last_failure) > STALE_AFTER {
+                entry.failures = 0;
+            }
+            entry.failures += 1;
+            entry.last_failure = now;
+            entry.blocked_until = block_duration(limits, entry.failures).map(|d| now + d);
+        }
+    }
+
+    pub fn record_success(&self, keys: &[AttemptKey]) {
+        let mut attempts = self.attempts.lock().unwrap();
+        for key in keys {
+            attempts.remove(key);
+        }
+    }
+}
+
+fn block_duration(limits: &Limits, failures: u32) -> Option<Duration> {
+    if failures >= limits.lockout_after {
+        Some(limits.lockout)
+    } else if failures > limits.free_attempts {
+        let exponent = (failures - limits.free_attempts - 1).min(16);
+        Some((limits.base_delay * 2u32.pow(exponent)).min(limits.lockout))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,56 @@
+use chrono::Utc;
+use rand::Rng;
+use reqwest::Url;
+use ring::hmac;
+
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+/// Steps accepted on either side of the current one to tolerate clock drift.
+const ALLOWED_DRIFT: i64 = 1;
+const BASE32: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+/// A fresh 160-bit secret, base32 encoded as authenticator apps expect.
+pub fn generate_secret() -> String {
+    base32::encode(BASE32, &rand::thread_rng().gen::<[u8; 20]>())
+}
+
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    let mut uri = Url::parse("otpauth://totp/").expect("valid otpauth base");
+    uri.set_path(&format!("{}:{}", issuer, account));
+    uri.query_pairs_mut()
+        .append_pair("secret", secret)
+        .append_pair("issuer", issuer)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &STEP_SECONDS.to_string());
+    uri.to_string()
+}
+
+fn code_at(key: &hmac::Key, step: i64) -> u32 {
+    let digest = hmac::sign(key, &step.to_be_bytes());
+    let digest = digest.as_ref();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    binary % 10u32.pow(DIGITS)
+}
+
+/// Checks an RFC 6238 code and returns the time step it matched, so callers can refuse
+/// a step that was already used.
+pub fn verify(secret: &str, code: &str) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let key = hmac::Key::new(
+        hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+        &base32::decode(BASE32, secret)?,
+    );
+    let current = Utc::now().timestamp() / STEP_SECONDS;
+    (current - ALLOWED_DRIFT..=current + ALLOWED_DRIFT).find(|step| code_at(&key, *step) == code)
+}
@@ -0,0 +1,535 @@
+use ciborium::value::Value;
+use rand::Rng;
+use ring::hmac;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+pub const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+const COSE_ALG_ES256: i128 = -7;
+const COSE_ALG_EDDSA: i128 = -8;
+const COSE_ALG_RS256: i128 = -257;
+
+/// Algorithms offered to authenticators, in order of preference.
+pub const SUPPORTED_ALGORITHMS: [i64; 3] = [-7, -8, -257];
+
+#[derive(Error, Debug)]
+pub enum WebauthnError {
+    #[error("unknown or expired challenge")]
+    UnknownChallenge,
+    #[error("invalid client data: {0}")]
+    InvalidClientData(&'static str),
+    #[error("invalid authenticator data: {0}")]
+    InvalidAuthenticatorData(&'static str),
+    #[error("unsupported credential public key")]
+    UnsupportedKey,
+    #[error("signature verification failed")]
+    InvalidSignature,
+}
+
+#[derive(PartialEq)]
+enum Ceremony {
+    Registration(i32),
+    Authentication,
+}
+
+struct PendingChallenge {
+    ceremony: Ceremony,
+    started: Instant,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony_type: String,
+    challenge: String,
+    origin: String,
+}
+
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    attested_credential: &'a [u8],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewCredential {
+    pub credential_id: String,
+    /// The credential public key as a COSE_Key structure.
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+}
+
+enum PublicKey {
+    Es256(Vec<u8>),
+    Ed25519(Vec<u8>),
+    Rs256 { n: Vec<u8>, e: Vec<u8> },
+}
+
+/// Relying party settings and the challenges handed out for ceremonies that are in flight.
+/// Attestation statements are not verified, so any authenticator (including software ones) is accepted.
+pub struct Webauthn {
+    pub rp_id: String,
+    pub rp_name: String,
+    origin: String,
+    fake_credential_key: hmac::Key,
+    pending: Mutex<HashMap<String, PendingChallenge>>,
+}
+
+pub type SharedWebauthn = Arc<Webauthn>;
+
+pub fn encode(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn decode(data: &str) -> Option<Vec<u8>> {
+    base64::decode_config(data.trim_end_matches('='), base64::URL_SAFE_NO_PAD).ok()
+}
+
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData<'_>, WebauthnError> {
+    if data.len() < 37 {
+        return Err(WebauthnError::InvalidAuthenticatorData("too short"));
+    }
+    Ok(AuthenticatorData {
+        rp_id_hash: &data[..32],
+        flags: data[32],
+        sign_count: u32::from_be_bytes([data[33], data[34], data[35], data[36]]),
+        attested_credential: &data[37..],
+    })
+}
+
+fn cose_label(map: &[(Value, Value)], label: i128) -> Option<&Value> {
+    map.iter()
+        .find(|(key, _)| key.as_integer().map(i128::from) == Some(label))
+        .map(|(_, value)| value)
+}
+
+fn cose_bytes(map: &[(Value, Value)], label: i128) -> Result<Vec<u8>, WebauthnError> {
+    cose_label(map, label)
+        .and_then(|value| value.as_bytes())
+        .cloned()
+        .ok_or(WebauthnError::UnsupportedKey)
+}
+
+fn parse_public_key(cose_key: &[u8]) -> Result<PublicKey, WebauthnError> {
+    let value: Value = ciborium::de::from_reader(cose_key).map_err(|_| WebauthnError::UnsupportedKey)?;
+    let map = value.as_map().ok_or(WebauthnError::UnsupportedKey)?;
+    let alg = cose_label(map, 3)
+        .and_then(|alg| alg.as_integer())
+        .map(i128::from)
+        .ok_or(WebauthnError::UnsupportedKey)?;
+    match alg {
+        COSE_ALG_ES256 => {
+            let mut point = vec![0x04];
+            point.extend(cose_bytes(map, -2)?);
+            point.extend(cose_bytes(map, -3)?);
+            Ok(PublicKey::Es256(point))
+        }
+        COSE_ALG_EDDSA => Ok(PublicKey::Ed25519(cose_bytes(map, -2)?)),
+        COSE_ALG_RS256 => Ok(PublicKey::Rs256 {
+            n: cose_bytes(map, -1)?,
+            e: cose_bytes(map, -2)?,
+        }),
+        _ => Err(WebauthnError::UnsupportedKey),
+    }
+}
+
+impl PublicKey {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            PublicKey::Es256(point) => UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, point)
+                .verify(message, signature)
+                .is_ok(),
+            PublicKey::Ed25519(key) => UnparsedPublicKey::new(&signature::ED25519, key)
+                .verify(message, signature)
+                .is_ok(),
+            PublicKey::Rs256 { n, e } => RsaPublicKeyComponents { n, e }
+                .verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, signature)
+                .is_ok(),
+        }
+    }
+}
+
+impl Webauthn {
+    /// `WEBAUTHN_RP_ID` must be the domain the front end is served from and
+    /// `WEBAUTHN_ORIGIN` its full origin. `WEBAUTHN_FAKE_CREDENTIAL_SECRET` keeps the stand-in
+    /// credential ids stable across restarts; without it they change with every start.
+    pub fn from_env() -> Webauthn {
+        let fake_credential_secret = env::var("WEBAUTHN_FAKE_CREDENTIAL_SECRET")
+            .map(String::into_bytes)
+            .unwrap_or_else(|_| rand::thread_rng().gen::<[u8; 32]>().to_vec());
+        Webauthn::new(
+            env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| "localhost".to_string()),
+            env::var("WEBAUTHN_RP_NAME").unwrap_or_else(|_| "Parkings".to_string()),
+            env::var("WEBAUTHN_ORIGIN").unwrap_or_else(|_| "http://localhost:8080".to_string()),
+            &fake_credential_secret,
+        )
+    }
+
+    pub fn new(rp_id: String, rp_name: String, origin: String, fake_credential_secret: &[u8]) -> Webauthn {
+        Webauthn {
+            rp_id,
+            rp_name,
+            origin,
+            fake_credential_key: hmac::Key::new(hmac::HMAC_SHA256, fake_credential_secret),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A credential id that stands in for logins that are unknown or have no passkey, so login
+    /// options look alike for every login. Always the same for a given login.
+    pub fn fake_credential_id(&self, login: &str) -> String {
+        encode(hmac::sign(&self.fake_credential_key, login.as_bytes()).as_ref())
+    }
+
+    fn issue_challenge(&self, ceremony: Ceremony) -> String {
+        let challenge = encode(&rand::thread_rng().gen::<[u8; 32]>());
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, p| p.started.elapsed() < CHALLENGE_TIMEOUT);
+        pending.insert(
+            challenge.clone(),
+            PendingChallenge {
+                ceremony,
+                started: Instant::now(),
+            },
+        );
+        challenge
+    }
+
+    pub fn registration_challenge(&self, user_id: i32) -> String {
+        self.issue_challenge(Ceremony::Registration(user_id))
+    }
+
+    pub fn authentication_challenge(&self) -> String {
+        self.issue_challenge(Ceremony::Authentication)
+    }
+
+    /// Checks type, origin and challenge of the client data; the challenge can only be used once.
+    fn check_client_data(
+        &self,
+        client_data_json: &[u8],
+        expected_type: &str,
+        ceremony: Ceremony,
+    ) -> Result<(), WebauthnError> {
+        let client_data: ClientData = serde_json::from_slice(client_data_json)
+            .map_err(|_| WebauthnError::InvalidClientData("malformed"))?;
+        if client_data.ceremony_type != expected_type {
+            return Err(WebauthnError::InvalidClientData("wrong type"));
+        }
+        if client_data.origin != self.origin {
+            return Err(WebauthnError::InvalidClientData("wrong origin"));
+        }
+        self.pending
+            .lock()
+            .unwrap()
+            .remove(&client_data.challenge)
+            .filter(|p| p.started.elapsed() < CHALLENGE_TIMEOUT && p.ceremony == ceremony)
+            .map(|_| ())
+            .ok_or(WebauthnError::UnknownChallenge)
+    }
+
+    fn check_authenticator_data(&self, data: &AuthenticatorData) -> Result<(), WebauthnError> {
+        if data.rp_id_hash != Sha256::digest(self.rp_id.as_bytes()).as_slice() {
+            return Err(WebauthnError::InvalidAuthenticatorData("wrong relying party"));
+        }
+        if data.flags & FLAG_USER_PRESENT == 0 {
+            return Err(WebauthnError::InvalidAuthenticatorData("user not present"));
+        }
+        Ok(())
+    }
+
+    pub fn verify_registration(
+        &self,
+        user_id: i32,
+        client_data_json: &[u8],
+        attestation_object: &[u8],
+    ) -> Result<NewCredential, WebauthnError> {
+        self.check_client_data(client_data_json, "webauthn.create", Ceremony::Registration(user_id))?;
+
+        let attestation: Value = ciborium::de::from_reader(attestation_object)
+            .map_err(|_| WebauthnError::InvalidAuthenticatorData("malformed attestation"))?;
+        let auth_data = attestation
+            .as_map()
+            .and_then(|map| {
+                map.iter()
+                    .find(|(key, _)| key.as_text() == Some("authData"))
+                    .and_then(|(_, value)| value.as_bytes())
+            })
+            .ok_or(WebauthnError::InvalidAuthenticatorData("missing authData"))?;
+        let data = parse_authenticator_data(auth_data)?;
+        self.check_authenticator_data(&data)?;
+        if data.flags & FLAG_ATTESTED_CREDENTIAL == 0 || data.attested_credential.len() < 18 {
+            return Err(WebauthnError::InvalidAuthenticatorData("missing credential"));
+        }
+
+        // aaguid (16 bytes), credential id length (2 bytes), credential id, COSE key, extensions
+        let attested = data.attested_credential;
+        let id_length = u16::from_be_bytes([attested[16], attested[17]]) as usize;
+        if attested.len() < 18 + id_length {
+            return Err(WebauthnError::InvalidAuthenticatorData("truncated credential"));
+        }
+        let cose_key: Value = ciborium::de::from_reader(&attested[18 + id_length..])
+            .map_err(|_| WebauthnError::UnsupportedKey)?;
+        let mut public_key = Vec::new();
+        ciborium::ser::into_writer(&cose_key, &mut public_key).map_err(|_| WebauthnError::UnsupportedKey)?;
+        parse_public_key(&public_key)?;
+
+        Ok(NewCredential {
+            credential_id: encode(&attested[18..18 + id_length]),
+            public_key,
+            sign_count: data.sign_count as i64,
+        })
+    }
+
+    /// Verifies an assertion against a stored credential and returns the new signature counter.
+    pub fn verify_authentication(
+        &self,
+        client_data_json: &[u8],
+        authenticator_data: &[u8],
+        signature: &[u8],
+        public_key: &[u8],
+        stored_sign_count: i64,
+    ) -> Result<i64, WebauthnError> {
+        self.check_client_data(client_data_json, "webauthn.get", Ceremony::Authentication)?;
+        let data = parse_authenticator_data(authenticator_data)?;
+        self.check_authenticator_data(&data)?;
+
+        let mut message = authenticator_data.to_vec();
+        message.extend(Sha256::digest(client_data_json));
+        if !parse_public_key(public_key)?.verify(&message, signature) {
+            return Err(WebauthnError::InvalidSignature);
+        }
+        // a counter that does not move forward hints at a cloned authenticator
+        let sign_count = data.sign_count as i64;
+        if (sign_count != 0 || stored_sign_count != 0) && sign_count <= stored_sign_count {
+            return Err(WebauthnError::InvalidAuthenticatorData("signature counter went backwards"));
+        }
+        Ok(sign_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+    use serde_json::json;
+
+    const RP_ID: &str = "parkings.example";
+    const ORIGIN: &str = "https://parkings.example";
+    const USER_ID: i32 = 42;
+
+    /// A software authenticator holding a single ES256 credential.
+    struct SoftwareAuthenticator {
+        credential_id: Vec<u8>,
+        key_pair: EcdsaKeyPair,
+        sign_count: u32,
+        rng: SystemRandom,
+    }
+
+    impl SoftwareAuthenticator {
+        fn new() -> SoftwareAuthenticator {
+            let rng = SystemRandom::new();
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+            SoftwareAuthenticator {
+                credential_id: rand::thread_rng().gen::<[u8; 16]>().to_vec(),
+                key_pair: EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref()).unwrap(),
+                sign_count: 0,
+                rng,
+            }
+        }
+
+        fn cose_key(&self) -> Vec<u8> {
+            let point = self.key_pair.public_key().as_ref();
+            let key = Value::Map(vec![
+                (Value::Integer(1.into()), Value::Integer(2.into())),
+                (Value::Integer(3.into()), Value::Integer((-7).into())),
+                (Value::Integer((-1).into()), Value::Integer(1.into())),
+                (Value::Integer((-2).into()), Value::Bytes(point[1..33].to_vec())),
+                (Value::Integer((-3).into()), Value::Bytes(point[33..].to_vec())),
+            ]);
+            let mut encoded = Vec::new();
+            ciborium::ser::into_writer(&key, &mut encoded).unwrap();
+            encoded
+        }
+
+        fn authenticator_data(&self, flags: u8, attested_credential: &[u8]) -> Vec<u8> {
+            let mut data = Sha256::digest(RP_ID.as_bytes()).to_vec();
+            data.push(flags);
+            data.extend(self.sign_count.to_be_bytes());
+            data.extend(attested_credential);
+            data
+        }
+
+        /// Returns `clientDataJSON` and the attestation object for `navigator.credentials.create`.
+        fn create(&self, challenge: &str) -> (Vec<u8>, Vec<u8>) {
+            let mut attested = vec![0u8; 16];
+            attested.extend((self.credential_id.len() as u16).to_be_bytes());
+            attested.extend(&self.credential_id);
+            attested.extend(self.cose_key());
+            let attestation = Value::Map(vec![
+                (Value::Text("fmt".to_string()), Value::Text("none".to_string())),
+                (Value::Text("attStmt".to_string()), Value::Map(Vec::new())),
+                (
+                    Value::Text("authData".to_string()),
+                    Value::Bytes(self.authenticator_data(FLAG_USER_PRESENT | FLAG_ATTESTED_CREDENTIAL, &attested)),
+                ),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::ser::into_writer(&attestation, &mut attestation_object).unwrap();
+            (client_data("webauthn.create", challenge, ORIGIN), attestation_object)
+        }
+
+        /// Returns `clientDataJSON`, authenticator data and signature for `navigator.credentials.get`.
+        fn get(&mut self, challenge: &str) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+            self.sign_count += 1;
+            let client_data_json = client_data("webauthn.get", challenge, ORIGIN);
+            let authenticator_data = self.authenticator_data(FLAG_USER_PRESENT, &[]);
+            let mut message = authenticator_data.clone();
+            message.extend(Sha256::digest(&client_data_json));
+            let signature = self.key_pair.sign(&self.rng, &message).unwrap().as_ref().to_vec();
+            (client_data_json, authenticator_data, signature)
+        }
+    }
+
+    fn client_data(ceremony_type: &str, challenge: &str, origin: &str) -> Vec<u8> {
+        serde_json::to_vec(&json!({ "type": ceremony_type, "challenge": challenge, "origin": origin })).unwrap()
+    }
+
+    fn relying_party() -> Webauthn {
+        Webauthn::new(RP_ID.to_string(), "Parkings".to_string(), ORIGIN.to_string(), b"test secret")
+    }
+
+    fn register(webauthn: &Webauthn, authenticator: &SoftwareAuthenticator) -> NewCredential {
+        let challenge = webauthn.registration_challenge(USER_ID);
+        let (client_data_json, attestation_object) = authenticator.create(&challenge);
+        webauthn
+            .verify_registration(USER_ID, &client_data_json, &attestation_object)
+            .unwrap()
+    }
+
+    fn sign_in(
+        webauthn: &Webauthn,
+        authenticator: &mut SoftwareAuthenticator,
+        credential: &NewCredential,
+        stored_sign_count: i64,
+    ) -> Result<i64, WebauthnError> {
+        let (client_data_json, authenticator_data, signature) = authenticator.get(&webauthn.authentication_challenge());
+        webauthn.verify_authentication(
+            &client_data_json,
+            &authenticator_data,
+            &signature,
+            &credential.public_key,
+            stored_sign_count,
+        )
+    }
+
+    #[test]
+    fn registers_and_authenticates_a_software_authenticator() {
+        let webauthn = relying_party();
+        let mut authenticator = SoftwareAuthenticator::new();
+        let credential = register(&webauthn, &authenticator);
+        assert_eq!(credential.credential_id, encode(&authenticator.credential_id));
+        assert_eq!(credential.sign_count, 0);
+
+        assert_eq!(sign_in(&webauthn, &mut authenticator, &credential, 0).unwrap(), 1);
+        assert_eq!(sign_in(&webauthn, &mut authenticator, &credential, 1).unwrap(), 2);
+    }
+
+    #[test]
+    fn registration_challenge_belongs_to_one_user() {
+        let webauthn = relying_party();
+        let authenticator = SoftwareAuthenticator::new();
+        let (client_data_json, attestation_object) = authenticator.create(&webauthn.registration_challenge(USER_ID));
+        assert!(matches!(
+            webauthn.verify_registration(USER_ID + 1, &client_data_json, &attestation_object),
+            Err(WebauthnError::UnknownChallenge)
+        ));
+    }
+
+    #[test]
+    fn rejects_reused_challenges() {
+        let webauthn = relying_party();
+        let mut authenticator = SoftwareAuthenticator::new();
+        let credential = register(&webauthn, &authenticator);
+        let key = &credential.public_key;
+
+        let (client_data_json, authenticator_data, signature) = authenticator.get(&webauthn.authentication_challenge());
+        assert!(webauthn
+            .verify_authentication(&client_data_json, &authenticator_data, &signature, key, 0)
+            .is_ok());
+        assert!(matches!(
+            webauthn.verify_authentication(&client_data_json, &authenticator_data, &signature, key, 0),
+            Err(WebauthnError::UnknownChallenge)
+        ));
+
+        // nor can a registration challenge be spent on a sign-in
+        let (client_data_json, authenticator_data, signature) =
+            authenticator.get(&webauthn.registration_challenge(USER_ID));
+        assert!(matches!(
+            webauthn.verify_authentication(&client_data_json, &authenticator_data, &signature, key, 1),
+            Err(WebauthnError::UnknownChallenge)
+        ));
+    }
+
+    #[test]
+    fn rejects_signature_counter_regression() {
+        let webauthn = relying_party();
+        let mut authenticator = SoftwareAuthenticator::new();
+        let credential = register(&webauthn, &authenticator);
+        authenticator.sign_count = 4;
+
+        // the authenticator now signs with 5, then 6, then 7
+        assert!(matches!(
+            sign_in(&webauthn, &mut authenticator, &credential, 5),
+            Err(WebauthnError::InvalidAuthenticatorData(_))
+        ));
+        assert!(matches!(
+            sign_in(&webauthn, &mut authenticator, &credential, 9),
+            Err(WebauthnError::InvalidAuthenticatorData(_))
+        ));
+        assert_eq!(sign_in(&webauthn, &mut authenticator, &credential, 6).unwrap(), 7);
+    }
+
+    #[test]
+    fn rejects_wrong_origin_and_bad_signatures() {
+        let webauthn = relying_party();
+        let mut authenticator = SoftwareAuthenticator::new();
+        let credential = register(&webauthn, &authenticator);
+        let key = &credential.public_key;
+
+        let challenge = webauthn.authentication_challenge();
+        let (_, authenticator_data, signature) = authenticator.get(&challenge);
+        let phished = client_data("webauthn.get", &challenge, "https://parkings.example.evil");
+        assert!(matches!(
+            webauthn.verify_authentication(&phished, &authenticator_data, &signature, key, 0),
+            Err(WebauthnError::InvalidClientData(_))
+        ));
+
+        let (client_data_json, authenticator_data, mut signature) =
+            authenticator.get(&webauthn.authentication_challenge());
+        let last = signature.len() - 1;
+        signature[last] ^= 1;
+        assert!(matches!(
+            webauthn.verify_authentication(&client_data_json, &authenticator_data, &signature, key, 0),
+            Err(WebauthnError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn fake_credential_ids_are_stable_per_login() {
+        let webauthn = relying_party();
+        assert_eq!(webauthn.fake_credential_id("alice"), relying_party().fake_credential_id("alice"));
+        assert_ne!(webauthn.fake_credential_id("alice"), webauthn.fake_credential_id("bob"));
+    }
+}